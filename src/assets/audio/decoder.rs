@@ -0,0 +1,434 @@
+use std::cell::Cell;
+use std::fs::File;
+use std::io::{self, Read, Seek, SeekFrom};
+use std::mem;
+use std::path::Path;
+use std::rc::Rc;
+
+use claxon;
+use hound;
+use minimp3;
+use vorbis;
+
+pub type Result<T> = ::std::result::Result<T, ::failure::Error>;
+
+/// The extensions of audio files that could be decoded.
+pub const EXTENSIONS: [&str; 4] = ["mp3", "wav", "flac", "ogg"];
+
+/// The number of samples that decoded in each chunk.
+const CHUNK: usize = 64 * 1024;
+
+/// A streaming decoder that produces interleaved samples in range [-1, 1] chunk by chunk,
+/// so we never have to keep the whole clip in memory.
+pub trait Source {
+    /// Gets the number of channels.
+    fn channels(&self) -> u8;
+
+    /// Gets the sample rate.
+    fn rate(&self) -> u64;
+
+    /// Gets the bit depth of samples. Lossy formats are always decoded into 16-bit.
+    fn bits(&self) -> u16;
+
+    /// Replaces the content of `buf` with next chunk of samples. Returns false if we reached
+    /// the end of stream.
+    fn next(&mut self, buf: &mut Vec<f32>) -> Result<bool>;
+}
+
+/// Opens a streaming decoder based on the extension of file.
+pub fn open<T: AsRef<Path>>(path: T) -> Result<Box<Source>> {
+    let path = path.as_ref();
+    let file = File::open(path)?;
+
+    let source: Box<Source> = match path.extension().and_then(|v| v.to_str()) {
+        Some("mp3") => Box::new(Mp3Source::new(file)?),
+        Some("wav") => Box::new(WavSource::new(file)?),
+        Some("flac") => Box::new(FlacSource::new(file)?),
+        Some("ogg") => Box::new(VorbisSource::new(file)?),
+        _ => bail!("{} is not supported yet!", path.display()),
+    };
+
+    if source.channels() == 0 || source.rate() == 0 {
+        bail!("No audio frames could be decoded.");
+    }
+
+    Ok(source)
+}
+
+/// A reader that keeps track of the bytes consumed by decoder, so we could tell where
+/// the corrupted data is.
+struct Tracked<R> {
+    inner: R,
+    offset: Rc<Cell<u64>>,
+}
+
+impl<R> Tracked<R> {
+    fn new(inner: R) -> (Self, Rc<Cell<u64>>) {
+        let offset = Rc::new(Cell::new(0));
+        let reader = Tracked {
+            inner: inner,
+            offset: offset.clone(),
+        };
+
+        (reader, offset)
+    }
+}
+
+impl<R: Read> Read for Tracked<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let len = self.inner.read(buf)?;
+        self.offset.set(self.offset.get() + len as u64);
+        Ok(len)
+    }
+}
+
+impl<R: Seek> Seek for Tracked<R> {
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        let offset = self.inner.seek(pos)?;
+        self.offset.set(offset);
+        Ok(offset)
+    }
+}
+
+/// Gets the factor that maps signed integer samples with `bits` width into [-1, 1].
+fn int_scale(bits: u16) -> f32 {
+    1.0 / (1u64 << (bits - 1)) as f32
+}
+
+struct Mp3Source {
+    decoder: minimp3::Decoder<Tracked<File>>,
+    offset: Rc<Cell<u64>>,
+    channels: u8,
+    rate: u64,
+    // The first frame, which is decoded ahead to get the format of stream.
+    pending: Option<Vec<f32>>,
+}
+
+impl Mp3Source {
+    fn new(file: File) -> Result<Self> {
+        let (file, offset) = Tracked::new(file);
+        let mut source = Mp3Source {
+            decoder: minimp3::Decoder::new(file),
+            offset: offset,
+            channels: 0,
+            rate: 0,
+            pending: None,
+        };
+
+        let mut buf = Vec::new();
+        if !source.frame(&mut buf)? {
+            // MP3 has no length in its header, so a cut is only detected if no frame survives.
+            bail!("No MP3 frame could be decoded before byte {}.", source.offset.get());
+        }
+
+        source.pending = Some(buf);
+
+        Ok(source)
+    }
+
+    fn frame(&mut self, buf: &mut Vec<f32>) -> Result<bool> {
+        match self.decoder.next_frame() {
+            Ok(frame) => {
+                self.channels = frame.channels as u8;
+                self.rate = frame.sample_rate as u64;
+                buf.extend(frame.data.iter().map(|&v| f32::from(v) / 32768.0));
+                Ok(true)
+            }
+            Err(minimp3::Error::Eof) => Ok(false),
+            Err(err) => bail!(
+                "Corrupted MP3 frame near byte {}. {}",
+                self.offset.get(),
+                err
+            ),
+        }
+    }
+}
+
+impl Source for Mp3Source {
+    fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    fn bits(&self) -> u16 {
+        16
+    }
+
+    fn next(&mut self, buf: &mut Vec<f32>) -> Result<bool> {
+        buf.clear();
+        if let Some(pending) = self.pending.take() {
+            buf.extend(pending);
+        }
+
+        while buf.len() < CHUNK {
+            if !self.frame(buf)? {
+                break;
+            }
+        }
+
+        Ok(!buf.is_empty())
+    }
+}
+
+struct WavSource {
+    reader: hound::WavReader<Tracked<File>>,
+    offset: Rc<Cell<u64>>,
+    spec: hound::WavSpec,
+}
+
+impl WavSource {
+    fn new(file: File) -> Result<Self> {
+        let (file, offset) = Tracked::new(file);
+        let reader = match hound::WavReader::new(file) {
+            Ok(reader) => reader,
+            Err(err) => bail!("Corrupted WAV header near byte {}. {}", offset.get(), err),
+        };
+
+        Ok(WavSource {
+            spec: reader.spec(),
+            reader: reader,
+            offset: offset,
+        })
+    }
+}
+
+impl Source for WavSource {
+    fn channels(&self) -> u8 {
+        self.spec.channels as u8
+    }
+
+    fn rate(&self) -> u64 {
+        u64::from(self.spec.sample_rate)
+    }
+
+    fn bits(&self) -> u16 {
+        match self.spec.sample_format {
+            hound::SampleFormat::Float => 32,
+            hound::SampleFormat::Int => self.spec.bits_per_sample,
+        }
+    }
+
+    fn next(&mut self, buf: &mut Vec<f32>) -> Result<bool> {
+        buf.clear();
+
+        match self.spec.sample_format {
+            hound::SampleFormat::Float => {
+                for v in self.reader.samples::<f32>().take(CHUNK) {
+                    match v {
+                        Ok(v) => buf.push(v),
+                        Err(err) => bail!(
+                            "Corrupted WAV samples near byte {}. {}",
+                            self.offset.get(),
+                            err
+                        ),
+                    }
+                }
+            }
+            hound::SampleFormat::Int => {
+                let scale = int_scale(self.spec.bits_per_sample);
+                for v in self.reader.samples::<i32>().take(CHUNK) {
+                    match v {
+                        Ok(v) => buf.push(v as f32 * scale),
+                        Err(err) => bail!(
+                            "Corrupted WAV samples near byte {}. {}",
+                            self.offset.get(),
+                            err
+                        ),
+                    }
+                }
+            }
+        }
+
+        Ok(!buf.is_empty())
+    }
+}
+
+struct FlacSource {
+    reader: claxon::FlacReader<Tracked<File>>,
+    offset: Rc<Cell<u64>>,
+    block: Vec<i32>,
+}
+
+impl FlacSource {
+    fn new(file: File) -> Result<Self> {
+        let (file, offset) = Tracked::new(file);
+        let reader = match claxon::FlacReader::new(file) {
+            Ok(reader) => reader,
+            Err(err) => bail!("Corrupted FLAC header near byte {}. {}", offset.get(), err),
+        };
+
+        Ok(FlacSource {
+            reader: reader,
+            offset: offset,
+            block: Vec::new(),
+        })
+    }
+}
+
+impl Source for FlacSource {
+    fn channels(&self) -> u8 {
+        self.reader.streaminfo().channels as u8
+    }
+
+    fn rate(&self) -> u64 {
+        u64::from(self.reader.streaminfo().sample_rate)
+    }
+
+    fn bits(&self) -> u16 {
+        self.reader.streaminfo().bits_per_sample as u16
+    }
+
+    fn next(&mut self, buf: &mut Vec<f32>) -> Result<bool> {
+        buf.clear();
+
+        let scale = int_scale(self.reader.streaminfo().bits_per_sample as u16);
+        while buf.len() < CHUNK {
+            let block = mem::replace(&mut self.block, Vec::new());
+            let block = match self.reader.blocks().read_next_or_eof(block) {
+                Ok(Some(block)) => block,
+                Ok(None) => break,
+                Err(err) => bail!(
+                    "Corrupted FLAC frame near byte {}. {}",
+                    self.offset.get(),
+                    err
+                ),
+            };
+
+            for i in 0..block.duration() {
+                for ch in 0..block.channels() {
+                    buf.push(block.sample(ch, i) as f32 * scale);
+                }
+            }
+
+            self.block = block.into_buffer();
+        }
+
+        Ok(!buf.is_empty())
+    }
+}
+
+struct VorbisSource {
+    decoder: vorbis::Decoder<Tracked<File>>,
+    offset: Rc<Cell<u64>>,
+    channels: u8,
+    rate: u64,
+    // The first packet, which is decoded ahead to get the format of stream.
+    pending: Option<Vec<f32>>,
+}
+
+impl VorbisSource {
+    fn new(file: File) -> Result<Self> {
+        let (file, offset) = Tracked::new(file);
+        let decoder = match vorbis::Decoder::new(file) {
+            Ok(decoder) => decoder,
+            Err(err) => bail!("Corrupted Vorbis header near byte {}. {}", offset.get(), err),
+        };
+
+        let mut source = VorbisSource {
+            decoder: decoder,
+            offset: offset,
+            channels: 0,
+            rate: 0,
+            pending: None,
+        };
+
+        let mut buf = Vec::new();
+        if !source.packet(&mut buf)? {
+            bail!("No Vorbis packet could be decoded before byte {}.", source.offset.get());
+        }
+
+        source.pending = Some(buf);
+
+        Ok(source)
+    }
+
+    fn packet(&mut self, buf: &mut Vec<f32>) -> Result<bool> {
+        match self.decoder.packets().next() {
+            Some(Ok(packet)) => {
+                self.channels = packet.channels as u8;
+                self.rate = packet.rate;
+                buf.extend(packet.data.iter().map(|&v| f32::from(v) / 32768.0));
+                Ok(true)
+            }
+            Some(Err(err)) => bail!(
+                "Corrupted Vorbis packet near byte {}. {}",
+                self.offset.get(),
+                err
+            ),
+            None => Ok(false),
+        }
+    }
+}
+
+impl Source for VorbisSource {
+    fn channels(&self) -> u8 {
+        self.channels
+    }
+
+    fn rate(&self) -> u64 {
+        self.rate
+    }
+
+    fn bits(&self) -> u16 {
+        16
+    }
+
+    fn next(&mut self, buf: &mut Vec<f32>) -> Result<bool> {
+        buf.clear();
+        if let Some(pending) = self.pending.take() {
+            buf.extend(pending);
+        }
+
+        while buf.len() < CHUNK {
+            if !self.packet(buf)? {
+                break;
+            }
+        }
+
+        Ok(!buf.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+
+    fn fixture(name: &str) -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/assets/audio/fixtures").join(name)
+    }
+
+    /// Decodes the whole file, and returns the first error.
+    fn decode(name: &str) -> Result<()> {
+        let mut source = open(fixture(name))?;
+        let mut buf = Vec::new();
+        while source.next(&mut buf)? {}
+        Ok(())
+    }
+
+    #[test]
+    fn truncated() {
+        let table = [
+            ("truncated.wav", "Corrupted WAV samples near byte"),
+            ("truncated.flac", "Corrupted FLAC frame near byte"),
+            ("truncated.mp3", "No MP3 frame could be decoded before byte 300"),
+            ("truncated.ogg", "Corrupted Vorbis header near byte"),
+        ];
+
+        for &(name, expected) in &table {
+            match decode(name) {
+                Ok(_) => panic!("{} is decoded without error.", name),
+                Err(err) => assert!(
+                    err.to_string().starts_with(expected),
+                    "{}: {}",
+                    name,
+                    err
+                ),
+            }
+        }
+    }
+}
@@ -0,0 +1,263 @@
+//! Measures the integrated loudness of audio clips as described in ITU-R BS.1770 and
+//! EBU R128.
+
+use std::f64::consts::PI;
+
+/// The absolute gating threshold in LUFS.
+const ABSOLUTE_GATE: f64 = -70.0;
+/// The relative gating threshold in LU, which is applied below the ungated loudness.
+const RELATIVE_GATE: f64 = -10.0;
+
+/// A second order IIR filter in direct form I.
+#[derive(Debug, Clone, Copy)]
+struct Biquad {
+    b: [f64; 3],
+    a: [f64; 2],
+    x: [f64; 2],
+    y: [f64; 2],
+}
+
+impl Biquad {
+    fn new(b: [f64; 3], a: [f64; 2]) -> Self {
+        Biquad {
+            b: b,
+            a: a,
+            x: [0.0; 2],
+            y: [0.0; 2],
+        }
+    }
+
+    /// The high shelf filter that models the acoustic effects of head.
+    fn shelf(rate: f64) -> Self {
+        let f0 = 1681.974450955533;
+        let g = 3.999843853973347;
+        let q = 0.7071752369554196;
+
+        let k = (PI * f0 / rate).tan();
+        let vh = 10f64.powf(g / 20.0);
+        let vb = vh.powf(0.4996667741545416);
+        let a0 = 1.0 + k / q + k * k;
+
+        Biquad::new(
+            [
+                (vh + vb * k / q + k * k) / a0,
+                2.0 * (k * k - vh) / a0,
+                (vh - vb * k / q + k * k) / a0,
+            ],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        )
+    }
+
+    /// The RLB high pass filter.
+    fn highpass(rate: f64) -> Self {
+        let f0 = 38.13547087602444;
+        let q = 0.5003270373238773;
+
+        let k = (PI * f0 / rate).tan();
+        let a0 = 1.0 + k / q + k * k;
+
+        Biquad::new(
+            [1.0, -2.0, 1.0],
+            [2.0 * (k * k - 1.0) / a0, (1.0 - k / q + k * k) / a0],
+        )
+    }
+
+    fn process(&mut self, x: f64) -> f64 {
+        let y = self.b[0] * x + self.b[1] * self.x[0] + self.b[2] * self.x[1]
+            - self.a[0] * self.y[0]
+            - self.a[1] * self.y[1];
+
+        self.x = [x, self.x[0]];
+        self.y = [y, self.y[0]];
+        y
+    }
+}
+
+/// Loudness meter that accepts interleaved samples incrementally.
+pub struct LoudnessMeter {
+    channels: usize,
+    filters: Vec<(Biquad, Biquad)>,
+    weights: Vec<f64>,
+    // The length of 100ms sub-blocks in frames.
+    step: usize,
+    frames: usize,
+    energy: f64,
+    // The energies of last four sub-blocks, which are combined into 400ms gating blocks.
+    window: Vec<f64>,
+    blocks: Vec<f64>,
+    peak: f32,
+}
+
+impl LoudnessMeter {
+    pub fn new(channels: u8, rate: u64) -> Self {
+        let channels = channels as usize;
+        let filters = (0..channels)
+            .map(|_| (Biquad::shelf(rate as f64), Biquad::highpass(rate as f64)))
+            .collect();
+
+        // Surround channels are weighted with +1.5 dB, and the LFE channel is ignored.
+        let weights = (0..channels)
+            .map(|i| match (channels, i) {
+                (6, 3) => 0.0,
+                (6, 4) | (6, 5) => 1.41,
+                _ => 1.0,
+            }).collect();
+
+        LoudnessMeter {
+            channels: channels,
+            filters: filters,
+            weights: weights,
+            step: ::std::cmp::max(rate as usize / 10, 1),
+            frames: 0,
+            energy: 0.0,
+            window: Vec::new(),
+            blocks: Vec::new(),
+            peak: 0.0,
+        }
+    }
+
+    /// Feeds interleaved samples in range [-1, 1].
+    pub fn push(&mut self, samples: &[f32]) {
+        for frame in samples.chunks(self.channels) {
+            for (i, &v) in frame.iter().enumerate() {
+                self.peak = self.peak.max(v.abs());
+
+                let (ref mut shelf, ref mut highpass) = self.filters[i];
+                let v = highpass.process(shelf.process(f64::from(v)));
+                self.energy += self.weights[i] * v * v;
+            }
+
+            self.frames += 1;
+            if self.frames == self.step {
+                self.window.push(self.energy / self.step as f64);
+                if self.window.len() > 4 {
+                    self.window.remove(0);
+                }
+
+                if self.window.len() == 4 {
+                    let energy = self.window.iter().sum::<f64>() / 4.0;
+                    self.blocks.push(energy);
+                }
+
+                self.frames = 0;
+                self.energy = 0.0;
+            }
+        }
+    }
+
+    /// Discards the gating blocks that extend beyond the first `frames` frames, which is used
+    /// to exclude the trailing silence.
+    pub fn truncate(&mut self, frames: usize) {
+        let len = (frames / self.step).saturating_sub(3);
+        self.blocks.truncate(len);
+    }
+
+    /// Gets the integrated loudness in LUFS. Returns `None` if the clip is shorter than
+    /// 400ms or all the gating blocks are silent.
+    pub fn integrated(&self) -> Option<f64> {
+        let gated = Self::mean(self.blocks.iter().filter(|&&v| loudness(v) > ABSOLUTE_GATE))?;
+        let threshold = loudness(gated) + RELATIVE_GATE;
+
+        Self::mean(
+            self.blocks
+                .iter()
+                .filter(|&&v| loudness(v) > ABSOLUTE_GATE && loudness(v) > threshold),
+        ).map(loudness)
+    }
+
+    /// Gets the absolute sample peak in dBFS. Returns `None` if all the samples are zero.
+    pub fn peak(&self) -> Option<f64> {
+        if self.peak > 0.0 {
+            Some(20.0 * f64::from(self.peak).log10())
+        } else {
+            None
+        }
+    }
+
+    fn mean<'a, T: Iterator<Item = &'a f64>>(iter: T) -> Option<f64> {
+        let (sum, len) = iter.fold((0.0, 0u32), |(sum, len), v| (sum + v, len + 1));
+        if len > 0 {
+            Some(sum / f64::from(len))
+        } else {
+            None
+        }
+    }
+}
+
+fn loudness(energy: f64) -> f64 {
+    -0.691 + 10.0 * energy.log10()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Generates `secs` seconds of 997 Hz sine at `level` dBFS.
+    fn sine(rate: u64, level: f64, secs: f64) -> Vec<f32> {
+        let amplitude = 10f64.powf(level / 20.0);
+        (0..(rate as f64 * secs) as usize)
+            .map(|i| (amplitude * (2.0 * PI * 997.0 * i as f64 / rate as f64).sin()) as f32)
+            .collect()
+    }
+
+    #[test]
+    fn sine_wave() {
+        // A full scale sine of mono channel reads -3.01 LUFS, as described in BS.1770.
+        for &rate in &[44100, 48000] {
+            let mut meter = LoudnessMeter::new(1, rate);
+            meter.push(&sine(rate, -20.0, 5.0));
+
+            let loudness = meter.integrated().unwrap();
+            assert!((loudness + 23.01).abs() < 0.05, "{}", loudness);
+
+            let peak = meter.peak().unwrap();
+            assert!((peak + 20.0).abs() < 0.01, "{}", peak);
+        }
+
+        // Both channels are summed.
+        let mut meter = LoudnessMeter::new(2, 48000);
+        let samples: Vec<_> = sine(48000, -20.0, 5.0).iter().flat_map(|&v| vec![v, v]).collect();
+        meter.push(&samples);
+        let loudness = meter.integrated().unwrap();
+        assert!((loudness + 20.0).abs() < 0.05, "{}", loudness);
+    }
+
+    #[test]
+    fn gating() {
+        let mut meter = LoudnessMeter::new(1, 48000);
+        meter.push(&[0.0; 48000 * 2]);
+        assert_eq!(meter.integrated(), None);
+        assert_eq!(meter.peak(), None);
+
+        // The silent blocks are excluded by the absolute gate, and the quiet ones by the
+        // relative gate. Only the blocks that overlap both parts are left, which lower the
+        // loudness a bit. It would be about -26 LUFS without gating.
+        meter.push(&sine(48000, -20.0, 5.0));
+        meter.push(&sine(48000, -50.0, 5.0));
+        meter.push(&[0.0; 48000 * 2]);
+        let loudness = meter.integrated().unwrap();
+        assert!((loudness + 23.01).abs() < 0.3, "{}", loudness);
+
+        // Too short to fill a gating block.
+        let mut meter = LoudnessMeter::new(1, 48000);
+        meter.push(&sine(48000, -20.0, 0.3));
+        assert_eq!(meter.integrated(), None);
+        assert!(meter.peak().is_some());
+    }
+
+    #[test]
+    fn truncate() {
+        let mut meter = LoudnessMeter::new(1, 48000);
+        meter.push(&sine(48000, -20.0, 1.0));
+        assert_eq!(meter.blocks.len(), 7);
+
+        // Keeps the blocks that end within the first frames.
+        meter.truncate(48000);
+        assert_eq!(meter.blocks.len(), 7);
+        meter.truncate(24000 + 100);
+        assert_eq!(meter.blocks.len(), 2);
+        meter.truncate(1000);
+        assert_eq!(meter.blocks.len(), 0);
+        assert_eq!(meter.integrated(), None);
+    }
+}
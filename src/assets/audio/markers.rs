@@ -0,0 +1,452 @@
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{Cursor, Read, Seek, SeekFrom};
+use std::mem;
+use std::path::Path;
+
+use byteorder::{LittleEndian, ReadBytesExt};
+use claxon;
+
+use super::params::{CuePoint, LoopRegion};
+
+pub type Result<T> = ::std::result::Result<T, ::failure::Error>;
+
+/// The loop region and cue markers of audio clip.
+#[derive(Serialize, Deserialize, Debug, Clone, Default, PartialEq, Eq)]
+pub struct Markers {
+    pub loop_region: Option<LoopRegion>,
+    pub cues: Vec<CuePoint>,
+}
+
+impl Markers {
+    /// Reads markers from the `smpl` and `cue ` chunks of WAV files, or the `LOOPSTART`,
+    /// `LOOPLENGTH` and `LOOPEND` comments of Vorbis and FLAC files.
+    pub fn read<T: AsRef<Path>>(path: T) -> Result<Markers> {
+        let path = path.as_ref();
+        match path.extension().and_then(|v| v.to_str()) {
+            Some("wav") => Self::wav(&mut File::open(path)?),
+            Some("ogg") => Ok(Self::comments(&Self::ogg(&mut File::open(path)?)?)),
+            Some("flac") => {
+                let options = claxon::FlacReaderOptions {
+                    metadata_only: true,
+                    read_vorbis_comment: true,
+                };
+
+                let reader = claxon::FlacReader::new_ext(File::open(path)?, options)?;
+                let comments = reader
+                    .tags()
+                    .map(|(k, v)| (k.to_owned(), v.to_owned()))
+                    .collect::<Vec<_>>();
+
+                Ok(Self::comments(&comments))
+            }
+            _ => Ok(Markers::default()),
+        }
+    }
+
+    /// Shifts the markers after the leading `head` frames are trimmed, and drops the ones
+    /// that fall out of the remaining `len` frames.
+    pub fn trim(&mut self, head: u64, len: u64) {
+        self.loop_region = self.loop_region.and_then(|v| {
+            let start = v.start.saturating_sub(head);
+            let end = ::std::cmp::min(v.end.saturating_sub(head), len);
+            if start < end {
+                Some(LoopRegion {
+                    start: start,
+                    end: end,
+                })
+            } else {
+                None
+            }
+        });
+
+        self.cues.retain(|v| v.position >= head && v.position - head <= len);
+        for v in &mut self.cues {
+            v.position -= head;
+        }
+    }
+
+    fn comments(comments: &[(String, String)]) -> Markers {
+        let get = |name: &str| {
+            comments
+                .iter()
+                .find(|v| v.0.eq_ignore_ascii_case(name))
+                .and_then(|v| v.1.trim().parse::<u64>().ok())
+        };
+
+        let mut markers = Markers::default();
+        if let Some(start) = get("LOOPSTART") {
+            let end = get("LOOPLENGTH")
+                .map(|v| start + v)
+                .or_else(|| get("LOOPEND").map(|v| v + 1));
+
+            if let Some(end) = end {
+                markers.loop_region = Some(LoopRegion {
+                    start: start,
+                    end: end,
+                });
+            }
+        }
+
+        markers
+    }
+
+    fn ogg<R: Read>(file: &mut R) -> Result<Vec<(String, String)>> {
+        // Collects the identification and comment headers, which are the first two packets of
+        // Vorbis stream.
+        let mut packets = Vec::new();
+        let mut packet = Vec::new();
+        while packets.len() < 2 {
+            let mut header = [0; 27];
+            file.read_exact(&mut header)?;
+            if &header[0..4] != b"OggS" {
+                bail!("File is not a Ogg container.");
+            }
+
+            let mut segments = vec![0; header[26] as usize];
+            file.read_exact(&mut segments)?;
+
+            for len in segments {
+                let start = packet.len();
+                packet.resize(start + len as usize, 0);
+                file.read_exact(&mut packet[start..])?;
+
+                if len < 255 {
+                    packets.push(mem::replace(&mut packet, Vec::new()));
+                }
+            }
+        }
+
+        let mut packet = Cursor::new(&packets[1]);
+        let mut magic = [0; 7];
+        packet.read_exact(&mut magic)?;
+        if &magic != b"\x03vorbis" {
+            bail!("Can not find the comment header of Vorbis stream.");
+        }
+
+        let len = packet.read_u32::<LittleEndian>()?;
+        packet.seek(SeekFrom::Current(i64::from(len)))?;
+
+        let mut comments = Vec::new();
+        for _ in 0..packet.read_u32::<LittleEndian>()? {
+            let mut comment = vec![0; packet.read_u32::<LittleEndian>()? as usize];
+            packet.read_exact(&mut comment)?;
+
+            let comment = String::from_utf8_lossy(&comment);
+            if let Some(index) = comment.find('=') {
+                let (k, v) = comment.split_at(index);
+                comments.push((k.to_owned(), v[1..].to_owned()));
+            }
+        }
+
+        Ok(comments)
+    }
+
+    fn wav<R: Read + Seek>(file: &mut R) -> Result<Markers> {
+        let mut header = [0; 12];
+        file.read_exact(&mut header)?;
+        if &header[0..4] != b"RIFF" || &header[8..12] != b"WAVE" {
+            bail!("File is not a WAVE container.");
+        }
+
+        let mut markers = Markers::default();
+        let mut labels = HashMap::new();
+        let mut cues = Vec::new();
+
+        loop {
+            let mut id = [0; 4];
+            if file.read_exact(&mut id).is_err() {
+                break;
+            }
+
+            // Chunks are padded to even size.
+            let len = file.read_u32::<LittleEndian>()?;
+            let padded = u64::from(len) + u64::from(len & 1);
+
+            match &id {
+                b"smpl" | b"cue " | b"LIST" => {
+                    let mut chunk = vec![0; len as usize];
+                    file.read_exact(&mut chunk)?;
+                    file.seek(SeekFrom::Current(i64::from(len & 1)))?;
+
+                    let mut chunk = Cursor::new(chunk);
+                    match &id {
+                        b"smpl" => markers.loop_region = Self::wav_loop(&mut chunk)?,
+                        b"cue " => cues = Self::wav_cues(&mut chunk)?,
+                        _ => Self::wav_labels(&mut chunk, &mut labels)?,
+                    }
+                }
+                _ => {
+                    file.seek(SeekFrom::Current(padded as i64))?;
+                }
+            }
+        }
+
+        markers.cues = cues
+            .into_iter()
+            .map(|(id, position)| CuePoint {
+                name: labels
+                    .remove(&id)
+                    .unwrap_or_else(|| format!("cue{}", id)),
+                position: position,
+            }).collect();
+
+        Ok(markers)
+    }
+
+    fn wav_loop(chunk: &mut Cursor<Vec<u8>>) -> Result<Option<LoopRegion>> {
+        // Skips manufacturer, product, sample period, MIDI unity note, MIDI pitch fraction,
+        // SMPTE format and SMPTE offset.
+        chunk.seek(SeekFrom::Start(28))?;
+        let num_loops = chunk.read_u32::<LittleEndian>()?;
+        chunk.read_u32::<LittleEndian>()?;
+
+        if num_loops == 0 {
+            return Ok(None);
+        }
+
+        // Cue point ID and loop type.
+        chunk.read_u32::<LittleEndian>()?;
+        chunk.read_u32::<LittleEndian>()?;
+
+        // The end of loop is inclusive in `smpl` chunk.
+        let start = chunk.read_u32::<LittleEndian>()?;
+        let end = chunk.read_u32::<LittleEndian>()?;
+
+        Ok(Some(LoopRegion {
+            start: u64::from(start),
+            end: u64::from(end) + 1,
+        }))
+    }
+
+    fn wav_cues(chunk: &mut Cursor<Vec<u8>>) -> Result<Vec<(u32, u64)>> {
+        let mut cues = Vec::new();
+        for _ in 0..chunk.read_u32::<LittleEndian>()? {
+            let id = chunk.read_u32::<LittleEndian>()?;
+            // Skips position, data chunk ID, chunk start and block start.
+            chunk.seek(SeekFrom::Current(16))?;
+            let offset = chunk.read_u32::<LittleEndian>()?;
+            cues.push((id, u64::from(offset)));
+        }
+
+        Ok(cues)
+    }
+
+    fn wav_labels(chunk: &mut Cursor<Vec<u8>>, labels: &mut HashMap<u32, String>) -> Result<()> {
+        let mut tp = [0; 4];
+        chunk.read_exact(&mut tp)?;
+        if &tp != b"adtl" {
+            return Ok(());
+        }
+
+        let mut id = [0; 4];
+        while chunk.read_exact(&mut id).is_ok() {
+            let len = chunk.read_u32::<LittleEndian>()?;
+            let mut data = vec![0; len as usize];
+            chunk.read_exact(&mut data)?;
+            chunk.seek(SeekFrom::Current(i64::from(len & 1)))?;
+
+            if &id == b"labl" && data.len() >= 4 {
+                let cue = Cursor::new(&data[..4]).read_u32::<LittleEndian>()?;
+                let text = data[4..].split(|&v| v == 0).next().unwrap_or(&[]);
+                labels.insert(cue, String::from_utf8_lossy(text).into_owned());
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::fs;
+    use std::process;
+
+    use byteorder::{BigEndian, WriteBytesExt};
+
+    use super::*;
+
+    /// Appends a RIFF chunk, which is padded to even size.
+    fn chunk(data: &mut Vec<u8>, id: &[u8], contents: &[u8]) {
+        data.extend_from_slice(id);
+        data.write_u32::<LittleEndian>(contents.len() as u32).unwrap();
+        data.extend_from_slice(contents);
+        if contents.len() % 2 == 1 {
+            data.push(0);
+        }
+    }
+
+    /// Generates the comment header of Vorbis stream.
+    fn vorbis_comments(vendor: &str, comments: &[&str]) -> Vec<u8> {
+        let mut data = b"\x03vorbis".to_vec();
+        data.write_u32::<LittleEndian>(vendor.len() as u32).unwrap();
+        data.extend_from_slice(vendor.as_bytes());
+        data.write_u32::<LittleEndian>(comments.len() as u32).unwrap();
+        for v in comments {
+            data.write_u32::<LittleEndian>(v.len() as u32).unwrap();
+            data.extend_from_slice(v.as_bytes());
+        }
+
+        data
+    }
+
+    fn cue(name: &str, position: u64) -> CuePoint {
+        CuePoint {
+            name: name.to_owned(),
+            position: position,
+        }
+    }
+
+    #[test]
+    fn wav() {
+        let mut smpl = vec![0; 28];
+        for &v in &[1, 0, 0, 0, 100, 199, 0, 0] {
+            smpl.write_u32::<LittleEndian>(v).unwrap();
+        }
+
+        let mut cues = Vec::new();
+        cues.write_u32::<LittleEndian>(2).unwrap();
+        for &(id, offset) in &[(1, 50), (2, 300)] {
+            cues.write_u32::<LittleEndian>(id).unwrap();
+            cues.extend_from_slice(&[0; 4]);
+            cues.extend_from_slice(b"data");
+            cues.extend_from_slice(&[0; 8]);
+            cues.write_u32::<LittleEndian>(offset).unwrap();
+        }
+
+        // The label has odd size, so it's padded inside the list.
+        let mut labl = Vec::new();
+        labl.write_u32::<LittleEndian>(1).unwrap();
+        labl.extend_from_slice(b"boss\0");
+        let mut list = b"adtl".to_vec();
+        chunk(&mut list, b"labl", &labl);
+
+        let mut body = b"WAVE".to_vec();
+        chunk(&mut body, b"fmt ", &[0; 16]);
+        chunk(&mut body, b"junk", &[0; 3]);
+        chunk(&mut body, b"smpl", &smpl);
+        chunk(&mut body, b"cue ", &cues);
+        chunk(&mut body, b"LIST", &list);
+        chunk(&mut body, b"data", &[0; 8]);
+
+        let mut data = Vec::new();
+        chunk(&mut data, b"RIFF", &body);
+
+        let markers = Markers::wav(&mut Cursor::new(data)).unwrap();
+        assert_eq!(
+            markers,
+            Markers {
+                loop_region: Some(LoopRegion { start: 100, end: 200 }),
+                cues: vec![cue("boss", 50), cue("cue2", 300)],
+            }
+        );
+
+        assert!(Markers::wav(&mut Cursor::new(b"RIFF\0\0\0\0AVI ".to_vec())).is_err());
+    }
+
+    #[test]
+    fn ogg() {
+        // The comment header is longer than 255 bytes, so it's laced with several segments.
+        let vendor = "v".repeat(300);
+        let identification = [1; 30];
+        let comments = vorbis_comments(&vendor, &["TITLE=a=b", "LoopStart=100", "LOOPLENGTH=50"]);
+
+        let mut data = b"OggS".to_vec();
+        data.extend_from_slice(&[0; 22]);
+        let mut segments = vec![identification.len() as u8];
+        let mut len = comments.len();
+        while len >= 255 {
+            segments.push(255);
+            len -= 255;
+        }
+
+        segments.push(len as u8);
+        data.push(segments.len() as u8);
+        data.extend_from_slice(&segments);
+        data.extend_from_slice(&identification);
+        data.extend_from_slice(&comments);
+
+        let comments = Markers::ogg(&mut Cursor::new(data)).unwrap();
+        assert_eq!(comments[0], ("TITLE".to_owned(), "a=b".to_owned()));
+
+        let markers = Markers::comments(&comments);
+        assert_eq!(markers.loop_region, Some(LoopRegion { start: 100, end: 150 }));
+
+        // The `LOOPEND` is inclusive, and the loop is ignored without its end.
+        let comments = vec![
+            ("LOOPSTART".to_owned(), "10".to_owned()),
+            ("LOOPEND".to_owned(), "19".to_owned()),
+        ];
+
+        assert_eq!(
+            Markers::comments(&comments).loop_region,
+            Some(LoopRegion { start: 10, end: 20 })
+        );
+        assert_eq!(Markers::comments(&comments[..1]).loop_region, None);
+    }
+
+    #[test]
+    fn flac() {
+        let mut streaminfo = Vec::new();
+        streaminfo.write_u16::<BigEndian>(4096).unwrap();
+        streaminfo.write_u16::<BigEndian>(4096).unwrap();
+        streaminfo.extend_from_slice(&[0; 6]);
+        // 44100 Hz, 2 channels, 16 bits and 0 samples.
+        streaminfo.write_u64::<BigEndian>(44100 << 44 | 1 << 41 | 15 << 36).unwrap();
+        streaminfo.extend_from_slice(&[0; 16]);
+
+        let comments = vorbis_comments("crayon", &["LOOPSTART=44100", "LOOPLENGTH=22050"]);
+
+        let mut data = b"fLaC".to_vec();
+        data.write_u32::<BigEndian>(streaminfo.len() as u32).unwrap();
+        data.extend_from_slice(&streaminfo);
+        data.write_u32::<BigEndian>(0x8400_0000 | (comments.len() - 7) as u32).unwrap();
+        // The comments of FLAC have no leading packet type and magic.
+        data.extend_from_slice(&comments[7..]);
+
+        let path = env::temp_dir().join(format!("crayon-cli-{}-markers.flac", process::id()));
+        fs::write(&path, &data).unwrap();
+        let markers = Markers::read(&path);
+        fs::remove_file(&path).unwrap();
+
+        assert_eq!(
+            markers.unwrap().loop_region,
+            Some(LoopRegion {
+                start: 44100,
+                end: 66150,
+            })
+        );
+    }
+
+    #[test]
+    fn trim() {
+        let markers = Markers {
+            loop_region: Some(LoopRegion { start: 100, end: 200 }),
+            cues: vec![cue("a", 50), cue("b", 150), cue("c", 300)],
+        };
+
+        // Shifts the markers, and drops the cues out of range.
+        let mut v = markers.clone();
+        v.trim(80, 100);
+        assert_eq!(v.loop_region, Some(LoopRegion { start: 20, end: 100 }));
+        assert_eq!(v.cues, vec![cue("b", 70)]);
+
+        // Clamps the loop to the remaining frames.
+        let mut v = markers.clone();
+        v.trim(120, 1000);
+        assert_eq!(v.loop_region, Some(LoopRegion { start: 0, end: 80 }));
+        assert_eq!(v.cues, vec![cue("b", 30), cue("c", 180)]);
+
+        // Drops the loop that is cut off completely.
+        let mut v = markers.clone();
+        v.trim(250, 1000);
+        assert_eq!(v.loop_region, None);
+        assert_eq!(v.cues, vec![cue("c", 50)]);
+
+        let mut v = markers.clone();
+        v.trim(0, 100);
+        assert_eq!(v.loop_region, None);
+        assert_eq!(v.cues, vec![cue("a", 50)]);
+    }
+}
@@ -1,54 +1,126 @@
 mod params;
-pub use self::params::AudioImportParams;
+pub use self::params::{AudioImportParams, CuePoint, LoadType, LoopRegion, Normalization};
 
+mod loudness;
+use self::loudness::LoudnessMeter;
+
+mod decoder;
+
+mod markers;
+use self::markers::Markers;
+
+mod ogg;
+
+use std::cmp;
 use std::fs::{self, File};
-use std::io::{Read, Write};
+use std::io::{self, Write};
+use std::path::Path;
 
+use crayon::bincode;
 use crayon_audio::assets::clip_loader;
 
+use toml;
 use vorbis;
 
-use assets::{AssetImporter, AssetParams, ResourceType};
+use assets::{AssetImporter, AssetParams, CorruptedAsset, ResourceType};
 use platform::Compression;
 use workspace::database::{AssetIntermediateGenerator, AssetMetadataGenerator};
 
 pub type Result<T> = ::std::result::Result<T, ::failure::Error>;
 
+/// The highest peak in dBFS after loudness normalization, which keeps quiet but dynamic clips
+/// from being clipped.
+const PEAK_CEILING: f64 = -1.0;
+
 pub struct AudioImporter {}
 
 impl AssetImporter for AudioImporter {
+    fn version(&self) -> u32 {
+        1
+    }
+
     fn compile(&self, db: &mut AssetIntermediateGenerator) -> Result<()> {
         if !db.modified() && !db.intermediate_modified("clip") {
             return Ok(());
         }
 
-        let in_file = File::open(db.path()).unwrap();
+        let extension = db.name().extension().unwrap().to_str().unwrap().to_owned();
+        if !decoder::EXTENSIONS.contains(&extension.as_str()) {
+            bail!("{} is not supported yet!", extension);
+        }
 
-        let (data, channels, rate) = match db.name().extension().unwrap().to_str().unwrap() {
-            "mp3" => mp3(in_file),
-            "wav" => wav(in_file),
-            "flac" => flac(in_file),
-            "ogg" => vorbis(in_file),
-            v => bail!("{} is not supported yet!", v),
-        };
+        let params: AudioImportParams = db.params().into();
 
-        let bps = channels as u32 * rate as u32;
+        // Pass 1: Measures the levels and finds the silence at both ends.
+        let analysis = match analyze(&db.path(), &params) {
+            Ok(v) => v,
+            Err(err) => {
+                let msg = format!("Failed to decode audio file {}. {}", db.name().display(), err);
+                return Err(CorruptedAsset(msg).into());
+            }
+        };
 
         info!(
             "Compiles audio flie {}. (Channels: {}, SampleRate: {}, Len: {:.2}s, Size: {})",
             db.name().display(),
-            channels,
-            rate,
-            f64::from(data.len() as u32) / f64::from(bps),
-            data.len()
+            analysis.channels,
+            analysis.sample_rate,
+            analysis.frames as f64 / analysis.sample_rate as f64,
+            analysis.frames * u64::from(analysis.channels)
         );
 
-        let params: AudioImportParams = db.params().into();
-        let mut encoder = vorbis::Encoder::new(channels, rate, params.compression.into())?;
+        if params.normalization != Normalization::None && analysis.gain.is_none() {
+            warn!(
+                "Skips normalization of {} since it's too short or silent.",
+                db.name().display()
+            );
+        }
 
-        let mut out_file = File::create(&db.intermediate("clip", true))?;
-        out_file.write(encoder.encode(&data)?.as_slice())?;
-        out_file.write(encoder.flush()?.as_slice())?;
+        if analysis.limited {
+            warn!(
+                "Limits the gain of {} to {:.1} dB, so its peak stays under {} dBFS.",
+                db.name().display(),
+                analysis.gain.unwrap_or(0.0),
+                PEAK_CEILING
+            );
+        }
+
+        info!(
+            "Processes audio file {}. (Loudness: {:?} LUFS, Peak: {:?} dBFS, Gain: {:?} dB, Trimmed: {}/{})",
+            db.name().display(),
+            analysis.loudness,
+            analysis.peak,
+            analysis.gain,
+            analysis.trimmed_leading,
+            analysis.trimmed_trailing
+        );
+
+        let contents = toml::ser::to_string_pretty(&analysis)?;
+        fs::write(db.intermediate("analysis.toml", true), contents)?;
+
+        let mut markers = Markers::read(&db.path()).unwrap_or_else(|err| {
+            warn!("Failed to read markers of {}. {}", db.name().display(), err);
+            Markers::default()
+        });
+
+        if params.loop_region.is_some() {
+            markers.loop_region = params.loop_region;
+        }
+
+        if let Some(ref cues) = params.cues {
+            markers.cues = cues.clone();
+        }
+
+        let len = analysis.frames - analysis.trimmed_leading - analysis.trimmed_trailing;
+        markers.trim(analysis.trimmed_leading, len);
+
+        let contents = toml::ser::to_string_pretty(&markers)?;
+        fs::write(db.intermediate("markers.toml", true), contents)?;
+
+        // Pass 2: Decodes the file again and encodes it chunk by chunk.
+        if let Err(err) = encode(&db.path(), &db.intermediate("clip", true), &params, &analysis) {
+            bail!("Failed to encode audio file {}. {}", db.name().display(), err);
+        }
 
         Ok(())
     }
@@ -71,15 +143,31 @@ impl AssetImporter for AudioImporter {
             return Ok(());
         }
 
+        let clip = db.intermediate("clip", false);
+        if !clip.exists() {
+            bail!("The clip of {} is missing.", db.name().display());
+        }
+
         info!("Imports audio clip {}.", db.name().display());
+        db.record_format("Vorbis");
 
-        let mut in_file = File::open(db.intermediate("clip", false))?;
-        let mut buf = Vec::new();
-        in_file.read_to_end(&mut buf)?;
+        let markers: Markers = fs::read_to_string(db.intermediate("markers.toml", false))
+            .ok()
+            .and_then(|v| toml::de::from_str(&v).ok())
+            .unwrap_or_default();
 
+        let params: AudioImportParams = db.params().into();
+        let header = AudioClipHeader {
+            load_type: params.load_type,
+            loop_region: markers.loop_region,
+            cues: markers.cues,
+        };
+
+        let mut in_file = File::open(clip)?;
         let mut file = fs::File::create(db.resource(&name, true))?;
         file.write_all(&clip_loader::MAGIC)?;
-        file.write_all(&buf)?;
+        bincode::serialize_into(&mut file, &header)?;
+        io::copy(&mut in_file, &mut file)?;
 
         Ok(())
     }
@@ -95,79 +183,257 @@ impl From<Compression> for vorbis::VorbisQuality {
     }
 }
 
-fn mp3(file: File) -> (Vec<i16>, u8, u64) {
-    use minimp3;
+/// The header of audio clip resource, which is written between `clip_loader::MAGIC` and
+/// the encoded Vorbis stream.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct AudioClipHeader {
+    /// The way that this clip should be loaded at runtime.
+    pub load_type: LoadType,
+    /// The region that should be repeated seamlessly, in frames of the encoded clip.
+    pub loop_region: Option<LoopRegion>,
+    /// The named positions, in frames of the encoded clip.
+    pub cues: Vec<CuePoint>,
+}
+
+/// The measurements of audio clip, which are recorded in intermediates for auditing.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, Default)]
+pub struct AudioAnalysis {
+    /// The number of channels.
+    pub channels: u8,
+    /// The sample rate.
+    pub sample_rate: u64,
+    /// The number of frames in source file.
+    pub frames: u64,
+    /// The integrated loudness in LUFS before normalization.
+    pub loudness: Option<f64>,
+    /// The absolute sample peak in dBFS before normalization.
+    pub peak: Option<f64>,
+    /// The gain in dB that applied to samples.
+    pub gain: Option<f64>,
+    /// Whether the gain of loudness normalization is limited by the peak ceiling.
+    pub limited: bool,
+    /// The number of leading frames that trimmed as silence.
+    pub trimmed_leading: u64,
+    /// The number of trailing frames that trimmed as silence.
+    pub trimmed_trailing: u64,
+}
 
-    let mut data = Vec::new();
-    let mut channels = 0;
-    let mut rate = 0;
+/// Finds the silence at both ends and measures the level of remaining samples.
+fn analyze(path: &Path, params: &AudioImportParams) -> Result<AudioAnalysis> {
+    let mut source = decoder::open(path)?;
+    let channels = source.channels() as usize;
+    let threshold = 10f32.powf(params.silence_threshold as f32 / 20.0);
+
+    let mut meter = LoudnessMeter::new(source.channels(), source.rate());
+    let mut frames = 0;
+    let mut head = None;
+    let mut end = 0;
+
+    let mut buf = Vec::new();
+    while source.next(&mut buf)? {
+        for (i, frame) in buf.chunks(channels).enumerate() {
+            if !params.trim_silence || frame.iter().any(|&v| v.abs() > threshold) {
+                let index = frames + i as u64;
+                head = head.or(Some(index));
+                end = index + 1;
+            }
+        }
 
-    let mut decoder = minimp3::Decoder::new(file);
+        // Feeds the loudness meter since the first audible frame.
+        if let Some(head) = head {
+            let start = cmp::min(head.saturating_sub(frames) as usize * channels, buf.len());
+            meter.push(&buf[start..]);
+        }
 
-    while let Some(frame) = decoder.next_frame().ok() {
-        channels = frame.channels;
-        rate = frame.sample_rate;
-        data.extend(frame.data);
+        frames += (buf.len() / channels) as u64;
     }
 
-    (data, channels as u8, rate as u64)
-}
+    let head = head.unwrap_or(frames);
+    let end = cmp::max(end, head);
+    meter.truncate((end - head) as usize);
+
+    let mut analysis = AudioAnalysis {
+        channels: source.channels(),
+        sample_rate: source.rate(),
+        frames: frames,
+        loudness: meter.integrated(),
+        peak: meter.peak(),
+        gain: None,
+        limited: false,
+        trimmed_leading: head,
+        trimmed_trailing: frames - end,
+    };
+
+    let target = f64::from(params.normalization_target());
+    analysis.gain = match params.normalization {
+        Normalization::None => None,
+        Normalization::Peak => analysis.peak.map(|v| target - v),
+        Normalization::Loudness => analysis.loudness.map(|v| target - v),
+    };
+
+    // Limits the gain of loudness normalization, so the peak is never clipped.
+    if params.normalization == Normalization::Loudness {
+        if let (Some(gain), Some(peak)) = (analysis.gain, analysis.peak) {
+            if peak + gain > PEAK_CEILING {
+                analysis.gain = Some(PEAK_CEILING - peak);
+                analysis.limited = true;
+            }
+        }
+    }
 
-fn wav(file: File) -> (Vec<i16>, u8, u64) {
-    use hound;
+    Ok(analysis)
+}
 
-    let mut reader = hound::WavReader::new(file).unwrap();
-    let spec = reader.spec();
+/// Trims and normalizes the samples based on analysis, and encodes them into Vorbis stream.
+fn encode(src: &Path, dst: &Path, params: &AudioImportParams, analysis: &AudioAnalysis) -> Result<()> {
+    let mut source = decoder::open(src)?;
+    let channels = u64::from(source.channels());
+    let scale = 10f64.powf(analysis.gain.unwrap_or(0.0) / 20.0) as f32;
+
+    let head = analysis.trimmed_leading;
+    let end = analysis.frames - analysis.trimmed_trailing;
+
+    let mut encoder = vorbis::Encoder::new(source.channels(), source.rate(), params.compression.into())?;
+    // Trimming only drops samples, so the 16-bit sources are kept as is unless the gain is
+    // applied.
+    let mut dither = Dither::new(source.bits() > 16 || analysis.gain.is_some());
+    let mut out_file = File::create(dst)?;
+
+    let mut frames = 0;
+    let mut buf = Vec::new();
+    while source.next(&mut buf)? {
+        let len = buf.len() as u64 / channels;
+        let start = cmp::min(head.saturating_sub(frames), len);
+        let stop = cmp::min(end.saturating_sub(frames), len);
+
+        if start < stop {
+            let samples = &mut buf[(start * channels) as usize..(stop * channels) as usize];
+            for v in samples.iter_mut() {
+                *v *= scale;
+            }
 
-    let mut data = Vec::new();
+            // Every chunk from encoder holds whole pages, so they are written out at once.
+            let mut pages = encoder.encode(&dither.quantize(samples))?;
+            ogg::stabilize(&mut pages)?;
+            out_file.write_all(&pages)?;
+        }
 
-    for v in reader.samples() {
-        let v = v.unwrap();
-        data.push(v);
+        frames += len;
     }
 
-    (data, spec.channels as u8, spec.sample_rate as u64)
+    let mut pages = encoder.flush()?;
+    ogg::stabilize(&mut pages)?;
+    out_file.write_all(&pages)?;
+    Ok(())
 }
 
-fn vorbis(file: File) -> (Vec<i16>, u8, u64) {
-    let mut data = Vec::new();
-    let mut channels = 0;
-    let mut rate = 0;
-    let mut decoder = vorbis::Decoder::new(file).unwrap();
-    let packets = decoder.packets();
-    for p in packets {
-        match p {
-            Ok(packet) => {
-                channels = packet.channels as u8;
-                rate = packet.rate;
-                data.extend(&packet.data);
-            }
-            _ => {}
+/// Reduces samples to 16-bit with triangular dither, which turns the quantization error into
+/// constant low-level noise instead of distortion.
+struct Dither {
+    enabled: bool,
+    seed: u32,
+}
+
+impl Dither {
+    fn new(enabled: bool) -> Self {
+        // Uses a fixed seed, so importing the same file twice always generates the same clip.
+        Dither {
+            enabled: enabled,
+            seed: 0x9E37_79B9,
         }
     }
 
-    (data, channels, rate)
+    fn quantize(&mut self, samples: &[f32]) -> Vec<i16> {
+        samples
+            .iter()
+            .map(|&v| {
+                let noise = if self.enabled {
+                    self.random() - self.random()
+                } else {
+                    0.0
+                };
+
+                let v = (v * 32768.0 + noise).round();
+                v.max(-32768.0).min(32767.0) as i16
+            }).collect()
+    }
+
+    /// Generates a uniformly distributed number in [0, 1) with xorshift.
+    fn random(&mut self) -> f32 {
+        self.seed ^= self.seed << 13;
+        self.seed ^= self.seed >> 17;
+        self.seed ^= self.seed << 5;
+        self.seed as f32 / 4_294_967_296.0
+    }
 }
 
-fn flac(file: File) -> (Vec<i16>, u8, u64) {
-    use claxon;
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::f64::consts::PI;
+    use std::path::PathBuf;
+    use std::process;
+
+    use hound;
+
+    use super::*;
+
+    /// Writes a 16-bit mono WAV file of 997 Hz sine at `level` dBFS, which has a full scale
+    /// sample at `spike` if any.
+    fn fixture(name: &str, level: f64, spike: Option<usize>) -> PathBuf {
+        let path = env::temp_dir().join(format!("crayon-cli-{}-{}.wav", process::id(), name));
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 48000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
 
-    let mut reader = claxon::FlacReader::new(file).unwrap();
-    let channels = reader.streaminfo().channels as u8;
-    let rate = reader.streaminfo().sample_rate as u64;
+        let amplitude = 10f64.powf(level / 20.0) * 32767.0;
+        let mut writer = hound::WavWriter::create(&path, spec).unwrap();
+        for i in 0..48000 * 3 {
+            let v = if Some(i) == spike {
+                32767.0
+            } else {
+                amplitude * (2.0 * PI * 997.0 * i as f64 / 48000.0).sin()
+            };
 
-    let bits_per_sample = reader.streaminfo().bits_per_sample;
-    let mut data = Vec::new();
-    data.extend(reader.samples().map(|v| {
-        let v = v.unwrap();
-        if bits_per_sample == 16 {
-            v as i16
-        } else if bits_per_sample < 16 {
-            (v << (16 - bits_per_sample)) as i16
-        } else {
-            (v >> (bits_per_sample - 16)) as i16
+            writer.write_sample(v.round() as i16).unwrap();
         }
-    }));
 
-    (data, channels, rate)
+        writer.finalize().unwrap();
+        path
+    }
+
+    #[test]
+    fn peak_ceiling() {
+        let params = AudioImportParams {
+            normalization: Normalization::Loudness,
+            ..AudioImportParams::default()
+        };
+
+        let path = fixture("analyze", -40.0, None);
+        let analysis = analyze(&path, &params).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!((analysis.gain.unwrap() - 20.0).abs() < 0.1, "{:?}", analysis);
+        assert!(!analysis.limited);
+
+        // The gain of about 20 dB would push the spike far above the ceiling.
+        let path = fixture("analyze-limited", -40.0, Some(1000));
+        let analysis = analyze(&path, &params).unwrap();
+        assert!((analysis.loudness.unwrap() + 43.0).abs() < 0.5, "{:?}", analysis);
+        assert!((analysis.gain.unwrap() - PEAK_CEILING).abs() < 0.01, "{:?}", analysis);
+        assert!(analysis.limited);
+
+        // The peak normalization never exceeds the ceiling by itself.
+        let params = AudioImportParams {
+            normalization: Normalization::Peak,
+            ..AudioImportParams::default()
+        };
+
+        let analysis = analyze(&path, &params).unwrap();
+        fs::remove_file(&path).unwrap();
+        assert!((analysis.gain.unwrap() - PEAK_CEILING).abs() < 0.01, "{:?}", analysis);
+        assert!(!analysis.limited);
+    }
 }
@@ -0,0 +1,96 @@
+use byteorder::{ByteOrder, LittleEndian};
+
+pub type Result<T> = ::std::result::Result<T, ::failure::Error>;
+
+/// The serial number of the logical stream in encoded clips. Every clip holds only one stream,
+/// so it does not need to be unique.
+const SERIAL: u32 = 0;
+
+/// The length of page header without the segment table.
+const HEADER: usize = 27;
+
+/// Replaces the random serial number that encoder picks with a fixed one, and updates the
+/// checksums of pages, so encoding the same samples twice always generates the same stream.
+/// The `data` should only hold whole pages.
+pub fn stabilize(data: &mut [u8]) -> Result<()> {
+    let mut pos = 0;
+    while pos < data.len() {
+        if data.len() < pos + HEADER || &data[pos..pos + 4] != b"OggS" {
+            bail!("Corrupted Ogg page at byte {}.", pos);
+        }
+
+        let segments = HEADER + data[pos + 26] as usize;
+        if data.len() < pos + segments {
+            bail!("Corrupted Ogg page at byte {}.", pos);
+        }
+
+        let len = data[pos + HEADER..pos + segments]
+            .iter()
+            .fold(segments, |len, &v| len + v as usize);
+
+        if data.len() < pos + len {
+            bail!("Corrupted Ogg page at byte {}.", pos);
+        }
+
+        let page = &mut data[pos..pos + len];
+        LittleEndian::write_u32(&mut page[14..18], SERIAL);
+        LittleEndian::write_u32(&mut page[22..26], 0);
+        let checksum = crc(page);
+        LittleEndian::write_u32(&mut page[22..26], checksum);
+
+        pos += len;
+    }
+
+    Ok(())
+}
+
+/// Calculates the CRC-32 of page, which uses the polynomial 0x04C11DB7 without reflection.
+fn crc(page: &[u8]) -> u32 {
+    page.iter().fold(0, |crc, &v| {
+        (0..8).fold(crc ^ (u32::from(v) << 24), |crc, _| {
+            if crc & 0x8000_0000 != 0 {
+                (crc << 1) ^ 0x04C1_1DB7
+            } else {
+                crc << 1
+            }
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs;
+    use std::path::PathBuf;
+
+    use super::*;
+
+    /// Reads the first page of fixture, which holds the identification header.
+    fn page() -> Vec<u8> {
+        let path = PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("src/assets/audio/fixtures/truncated.ogg");
+        fs::read(path).unwrap()[..58].to_vec()
+    }
+
+    #[test]
+    fn checksum() {
+        let mut data = page();
+        let expected = LittleEndian::read_u32(&data[22..26]);
+        LittleEndian::write_u32(&mut data[22..26], 0);
+        assert_eq!(crc(&data), expected);
+    }
+
+    #[test]
+    fn serial() {
+        let mut a = page();
+        let mut b = page();
+        LittleEndian::write_u32(&mut b[14..18], 0xDEAD_BEEF);
+        b.extend(page());
+
+        stabilize(&mut a).unwrap();
+        stabilize(&mut b).unwrap();
+        assert_eq!(LittleEndian::read_u32(&a[14..18]), SERIAL);
+        assert_eq!(&b[..58], &a[..]);
+        assert_eq!(&b[58..], &a[..]);
+
+        assert!(stabilize(&mut page()[..40]).is_err());
+    }
+}
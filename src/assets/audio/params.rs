@@ -2,12 +2,51 @@ use assets::AssetParams;
 use platform::Compression;
 
 /// Settings of importing sound effect assets.
-#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub struct AudioImportParams {
     /// The optional override sample rate of imported sound effect.
     pub sample_rate: Option<SampleRate>,
     /// Compression level of imported sound effect.
     pub compression: Compression,
+    /// Normalizes the level of imported sound effect.
+    #[serde(default)]
+    pub normalization: Normalization,
+    /// The optional override target level of normalization, in dBFS for `Peak` and
+    /// LUFS for `Loudness`.
+    pub normalization_target: Option<i32>,
+    /// Removes the leading and trailing silence of imported sound effect.
+    #[serde(default)]
+    pub trim_silence: bool,
+    /// Samples below this level (in dBFS) are treated as silence when trimming.
+    #[serde(default = "AudioImportParams::default_silence_threshold")]
+    pub silence_threshold: i32,
+    /// The way that imported sound effect being loaded at runtime.
+    #[serde(default)]
+    pub load_type: LoadType,
+    /// The optional override loop region, which replaces the one read from source file.
+    pub loop_region: Option<LoopRegion>,
+    /// The optional override cue markers, which replace the ones read from source file.
+    pub cues: Option<Vec<CuePoint>>,
+}
+
+/// A region of sound effect that could be repeated seamlessly. The positions are measured
+/// in sample frames of source file.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct LoopRegion {
+    /// The first frame of loop.
+    pub start: u64,
+    /// The frame after the last frame of loop.
+    pub end: u64,
+}
+
+/// A named position of sound effect, measured in sample frames of source file.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
+pub struct CuePoint {
+    pub name: String,
+    pub position: u64,
 }
 
 /// List of common sample rates.
@@ -27,15 +66,74 @@ pub enum SampleRate {
     Hz96000,
 }
 
+/// List of level normalization methods.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum Normalization {
+    /// Keeps the level of source file.
+    None,
+    /// Scales the samples until the absolute peak reaches the target level (-1 dBFS by default).
+    Peak,
+    /// Scales the samples until the integrated loudness measured with EBU R128 reaches the
+    /// target level (-23 LUFS by default).
+    Loudness,
+}
+
+/// List of methods to load audio clips at runtime.
+#[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+pub enum LoadType {
+    /// Decompresses the whole clip into memory when it's loaded. This costs more memory but
+    /// has the lowest playback overhead, which is suitable for short sound effects.
+    DecompressOnLoad,
+    /// Keeps the clip compressed in memory and decodes it on the fly while playing, which is
+    /// suitable for long music tracks.
+    Streamed,
+}
+
+impl Default for LoadType {
+    fn default() -> Self {
+        LoadType::DecompressOnLoad
+    }
+}
+
+impl Default for Normalization {
+    fn default() -> Self {
+        Normalization::None
+    }
+}
+
 impl Default for AudioImportParams {
     fn default() -> Self {
         AudioImportParams {
             sample_rate: None,
             compression: Compression::HighQuality,
+            normalization: Normalization::None,
+            normalization_target: None,
+            trim_silence: false,
+            silence_threshold: Self::default_silence_threshold(),
+            load_type: LoadType::DecompressOnLoad,
+            loop_region: None,
+            cues: None,
         }
     }
 }
 
+impl AudioImportParams {
+    /// Gets the target level of normalization.
+    pub fn normalization_target(&self) -> i32 {
+        match self.normalization_target {
+            Some(v) => v,
+            None => match self.normalization {
+                Normalization::Loudness => -23,
+                _ => -1,
+            },
+        }
+    }
+
+    fn default_silence_threshold() -> i32 {
+        -60
+    }
+}
+
 impl From<AssetParams> for AudioImportParams {
     fn from(params: AssetParams) -> Self {
         match params {
@@ -8,6 +8,10 @@ pub type Result<T> = ::std::result::Result<T, ::failure::Error>;
 pub struct BytesImporter {}
 
 impl AssetImporter for BytesImporter {
+    fn version(&self) -> u32 {
+        1
+    }
+
     fn compile(&self, _: &mut AssetIntermediateGenerator) -> Result<()> {
         Ok(())
     }
@@ -14,6 +14,19 @@ use workspace::database::{AssetIntermediateGenerator, AssetMetadataGenerator};
 
 pub type Result<T> = ::std::result::Result<T, ::failure::Error>;
 
+/// The error of an asset file that could not be read, such as a truncated audio file. The
+/// asset is skipped and reported, and the other assets are still built.
+#[derive(Debug)]
+pub struct CorruptedAsset(pub String);
+
+impl ::std::fmt::Display for CorruptedAsset {
+    fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl ::failure::Fail for CorruptedAsset {}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Hash)]
 pub enum AssetType {
     Texture,
@@ -31,7 +44,7 @@ pub enum ResourceType {
     AudioClip,
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
 #[serde(tag = "type")]
 pub enum AssetParams {
     Bytes,
@@ -40,7 +53,11 @@ pub enum AssetParams {
     Audio(AudioImportParams),
 }
 
-pub trait AssetImporter {
+pub trait AssetImporter: Send + Sync {
+    /// Gets the version of importer. It should be bumped whenever the generated intermediates
+    /// or resources are changed, so the affected assets will be re-imported.
+    fn version(&self) -> u32;
+
     /// Compiles the assets into some kind of intermediate files for importing process.
     fn compile(&self, db: &mut AssetIntermediateGenerator) -> Result<()>;
 
@@ -23,6 +23,10 @@ pub type Result<T> = ::std::result::Result<T, ::failure::Error>;
 pub struct TextureImporter {}
 
 impl AssetImporter for TextureImporter {
+    fn version(&self) -> u32 {
+        1
+    }
+
     fn compile(&self, db: &mut AssetIntermediateGenerator) -> Result<()> {
         if !db.modified()
             && !db.intermediate_modified("source.ktx")
@@ -77,6 +81,7 @@ impl AssetImporter for TextureImporter {
         tex.filter = params.filter;
         tex.wrap = params.wrap;
         tex.format = format;
+        db.record_format(format!("{:?}", format));
 
         info!(
             "Imports resource {}.  Size: {:?}.",
@@ -4,6 +4,7 @@ use platform::{Compression, RuntimePlatform};
 
 /// Settings of importing texture assets.
 #[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub struct TextureImportParams {
     /// Select this to enable mip-map generation. Mip maps are smaller versions
     /// of the Texture that get used when the Texture is very small on screen.
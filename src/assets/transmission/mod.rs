@@ -6,6 +6,7 @@ pub use self::params::{MeshImportParams, TransmissionImportParams};
 
 use std::fs;
 use std::io::Write;
+use std::path::Path;
 use std::process::Command;
 
 use crayon::bincode;
@@ -14,6 +15,8 @@ use crayon::video::assets::mesh_loader;
 use crayon_world::assets::prefab::Prefab;
 use crayon_world::assets::prefab_loader;
 
+use serde_json;
+
 use super::{AssetImporter, AssetParams, ResourceType};
 
 use workspace::database::{AssetIntermediateGenerator, AssetMetadataGenerator};
@@ -23,7 +26,103 @@ pub type Result<T> = ::std::result::Result<T, ::failure::Error>;
 
 pub struct TransmissionImporter {}
 
+impl TransmissionImporter {
+    /// Registers the external files that referenced by glTF and OBJ files, since Assimp reads
+    /// them besides the asset itself.
+    fn dependencies(db: &mut AssetIntermediateGenerator) -> Result<()> {
+        let path = db.path();
+        let extension = path
+            .extension()
+            .and_then(|v| v.to_str())
+            .map(|v| v.to_lowercase());
+
+        match extension.as_ref().map(|v| v.as_str()) {
+            Some("gltf") => {
+                let json: serde_json::Value = serde_json::from_slice(&fs::read(&path)?)?;
+                for key in &["buffers", "images"] {
+                    if let Some(items) = json.get(key).and_then(|v| v.as_array()) {
+                        for v in items {
+                            if let Some(uri) = v.get("uri").and_then(|v| v.as_str()) {
+                                // Embedded data does not reference any file.
+                                if !uri.starts_with("data:") {
+                                    db.dependency(percent_decode(uri));
+                                }
+                            }
+                        }
+                    }
+                }
+            }
+            Some("obj") => {
+                for line in fs::read_to_string(&path)?.lines() {
+                    let line = line.trim();
+                    if !line.starts_with("mtllib ") {
+                        continue;
+                    }
+
+                    for lib in line["mtllib ".len()..].split_whitespace() {
+                        if let Some(mtl) = db.dependency(lib) {
+                            Self::mtl_dependencies(db, Path::new(lib), &mtl)?;
+                        }
+                    }
+                }
+            }
+            _ => {}
+        }
+
+        Ok(())
+    }
+
+    /// Registers the textures that referenced by OBJ material library.
+    fn mtl_dependencies(db: &mut AssetIntermediateGenerator, lib: &Path, path: &Path) -> Result<()> {
+        let contents = match fs::read_to_string(path) {
+            Ok(v) => v,
+            Err(_) => return Ok(()),
+        };
+
+        let dir = lib.parent().unwrap_or_else(|| Path::new(""));
+        for line in contents.lines() {
+            let mut tokens = line.split_whitespace();
+            let statement = tokens.next().unwrap_or("").to_lowercase();
+
+            if statement.starts_with("map_") || ["bump", "disp", "decal", "norm"].contains(&statement.as_str()) {
+                // The options of texture are placed before the file name.
+                if let Some(texture) = tokens.last() {
+                    db.dependency(dir.join(texture));
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn percent_decode(uri: &str) -> String {
+    let bytes = uri.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] == b'%' && i + 2 < bytes.len() {
+            let hex = ::std::str::from_utf8(&bytes[i + 1..i + 3]).ok();
+            if let Some(v) = hex.and_then(|v| u8::from_str_radix(v, 16).ok()) {
+                decoded.push(v);
+                i += 3;
+                continue;
+            }
+        }
+
+        decoded.push(bytes[i]);
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
 impl AssetImporter for TransmissionImporter {
+    fn version(&self) -> u32 {
+        1
+    }
+
     fn compile(&self, db: &mut AssetIntermediateGenerator) -> Result<()> {
         if !db.modified() && !db.intermediate_modified("transmission.assbin") {
             return Ok(());
@@ -31,6 +130,8 @@ impl AssetImporter for TransmissionImporter {
 
         info!("Compiles transmission file {}.", db.name().display());
 
+        Self::dependencies(db)?;
+
         let params: TransmissionImportParams = db.params().into();
 
         let mut cmd = Command::new(utils::current_exe_dir().join("utilities/assimp"));
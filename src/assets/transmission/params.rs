@@ -2,12 +2,14 @@ use assets::AssetParams;
 
 /// Settings of transmission importing.
 #[derive(Default, Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub struct TransmissionImportParams {
     pub mesh: MeshImportParams,
 }
 
 /// Settings of importing mesh resources.
 #[derive(Serialize, Deserialize, Debug, Copy, Clone, PartialEq, Eq)]
+#[serde(deny_unknown_fields)]
 pub struct MeshImportParams {
     /// The vertices and indices will be reordered for better GPU performance.
     /// Techniques that require strict vertex ordering like mesh morphing or
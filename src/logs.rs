@@ -0,0 +1,74 @@
+//! Captures the logs of concurrent jobs, so they could be printed in a deterministic order
+//! instead of interleaving with each other.
+
+use std::cell::RefCell;
+use std::io::{self, Write};
+use std::mem;
+
+use crayon::LevelFilter;
+use env_logger;
+
+thread_local! {
+    static CAPTURED: RefCell<Option<Vec<Record>>> = RefCell::new(None);
+}
+
+/// A captured log record.
+#[derive(Debug, Clone)]
+pub struct Record {
+    /// Whether this is a warning or error.
+    pub warning: bool,
+    /// The message without level and target.
+    pub message: String,
+    /// The formatted line.
+    pub line: String,
+}
+
+/// Initializes the logger, which prints to stderr unless the logs are being captured.
+pub fn init() {
+    env_logger::Builder::new()
+        .filter_level(LevelFilter::Info)
+        .format(|buf, record| {
+            let message = format!("{}", record.args());
+            let mut line = Some(format!(
+                "[{} {}] {}",
+                record.level(),
+                record.target(),
+                message
+            ));
+
+            CAPTURED.with(|v| {
+                if let Some(ref mut captured) = *v.borrow_mut() {
+                    captured.push(Record {
+                        warning: record.level() <= LevelFilter::Warn,
+                        message: message,
+                        line: line.take().unwrap(),
+                    });
+                }
+            });
+
+            match line {
+                Some(line) => writeln!(buf, "{}", line),
+                None => Ok(()),
+            }
+        }).init();
+}
+
+/// Runs `f` and collects the logs it produced on current thread.
+pub fn capture<F, R>(f: F) -> (R, Vec<Record>)
+where
+    F: FnOnce() -> R,
+{
+    let prev = CAPTURED.with(|v| mem::replace(&mut *v.borrow_mut(), Some(Vec::new())));
+    let result = f();
+    let captured = CAPTURED.with(|v| mem::replace(&mut *v.borrow_mut(), prev));
+    (result, captured.unwrap_or_default())
+}
+
+/// Prints the captured logs.
+pub fn flush(captured: &[Record]) {
+    let stderr = io::stderr();
+    let mut stderr = stderr.lock();
+    for v in captured {
+        let _ = writeln!(stderr, "{}", v.line);
+    }
+}
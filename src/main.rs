@@ -13,7 +13,9 @@ extern crate serde_json;
 extern crate toml;
 
 extern crate byteorder;
-extern crate crc;
+extern crate notify;
+extern crate rayon;
+extern crate twox_hash;
 extern crate uuid;
 extern crate walkdir;
 
@@ -26,46 +28,267 @@ extern crate minimp3;
 extern crate vorbis;
 
 pub mod assets;
+pub mod logs;
 pub mod platform;
 pub mod workspace;
 
 use crayon::errors::*;
 
-use clap::{App, Arg, ArgMatches, SubCommand};
-use std::path::Path;
+use clap::{App, AppSettings, Arg, ArgMatches, SubCommand};
+use std::path::{Path, PathBuf};
+
+use workspace::cache::CheckMode;
 
 fn main() -> Result<()> {
-    env_logger::Builder::new()
-        .filter_level(crayon::LevelFilter::Info)
-        .init();
+    logs::init();
 
     let matches = App::new("crayon-tools")
         .version("0.0.1")
         .subcommand(
             SubCommand::with_name("build")
                 .about("Builds assets into platform-dependent resources.")
+                .arg(path_arg())
+                .arg(cache_dir_arg())
+                .arg(profile_arg())
+                .arg(check_arg())
+                .arg(jobs_arg())
+                .arg(keep_going_arg())
+                .arg(reproducible_arg())
                 .arg(
-                    Arg::with_name("path")
-                        .short("p")
-                        .help("Sets the root path of workspace.")
+                    Arg::with_name("report")
+                        .long("report")
+                        .value_name("FILE")
+                        .help("Writes a JSON report of the build into file.")
                         .takes_value(true),
                 ),
+        ).subcommand(
+            SubCommand::with_name("watch")
+                .about("Builds assets, and rebuilds them incrementally whenever they are changed.")
+                .arg(path_arg())
+                .arg(cache_dir_arg())
+                .arg(profile_arg())
+                .arg(check_arg())
+                .arg(jobs_arg())
+                .arg(reproducible_arg()),
+        ).subcommand(
+            SubCommand::with_name("init")
+                .about("Creates a workspace with default settings.")
+                .arg(
+                    Arg::with_name("path")
+                        .help("Sets the root path of workspace, defaults to current directory.")
+                        .index(1),
+                ).arg(
+                    Arg::with_name("force")
+                        .short("f")
+                        .long("force")
+                        .help("Overwrites the existing workspace.toml."),
+                ),
+        ).subcommand(
+            SubCommand::with_name("check")
+                .about("Validates workspace.toml and meta-files without building.")
+                .arg(path_arg())
+                .arg(profile_arg()),
+        ).subcommand(
+            SubCommand::with_name("mv")
+                .about("Moves an asset or folder together with its meta-files, so the UUIDs of resources are kept.")
+                .arg(path_arg())
+                .arg(cache_dir_arg())
+                .arg(Arg::with_name("src").required(true).index(1))
+                .arg(Arg::with_name("dst").required(true).index(2)),
+        ).subcommand(
+            SubCommand::with_name("clean")
+                .about("Removes resources and intermediates of workspace, both are removed if neither is specified.")
+                .arg(path_arg())
+                .arg(cache_dir_arg())
+                .arg(profile_arg())
+                .arg(
+                    Arg::with_name("resources")
+                        .long("resources")
+                        .help("Removes the resources folder."),
+                ).arg(
+                    Arg::with_name("intermediates")
+                        .long("intermediates")
+                        .help("Removes the intermediates of workspace."),
+                ),
+        ).subcommand(
+            SubCommand::with_name("cache")
+                .about("Manages the intermediates of workspace.")
+                .setting(AppSettings::SubcommandRequiredElseHelp)
+                .subcommand(
+                    SubCommand::with_name("stats")
+                        .about("Prints the statistics of intermediates.")
+                        .arg(path_arg())
+                        .arg(cache_dir_arg())
+                        .arg(profile_arg()),
+                ).subcommand(
+                    SubCommand::with_name("prune")
+                        .about("Removes the intermediates of other workspaces and platforms.")
+                        .arg(path_arg())
+                        .arg(cache_dir_arg())
+                        .arg(profile_arg()),
+                ).subcommand(
+                    SubCommand::with_name("verify")
+                        .about("Verifies the checksums of intermediates, and drops the corrupted ones.")
+                        .arg(path_arg())
+                        .arg(cache_dir_arg())
+                        .arg(profile_arg()),
+                ),
         ).get_matches();
 
-    if let Some(matches) = matches.subcommand_matches("build") {
-        return cmd_build(matches);
+    match matches.subcommand() {
+        ("build", Some(matches)) => cmd_build(matches),
+        ("watch", Some(matches)) => cmd_watch(matches),
+        ("init", Some(matches)) => cmd_init(matches),
+        ("check", Some(matches)) => cmd_check(matches),
+        ("mv", Some(matches)) => cmd_mv(matches),
+        ("clean", Some(matches)) => cmd_clean(matches),
+        ("cache", Some(matches)) => cmd_cache(matches),
+        _ => Ok(()),
     }
+}
 
-    Ok(())
+fn path_arg() -> Arg<'static, 'static> {
+    Arg::with_name("path")
+        .short("p")
+        .help("Sets the root path of workspace.")
+        .takes_value(true)
+}
+
+fn cache_dir_arg() -> Arg<'static, 'static> {
+    Arg::with_name("cache-dir")
+        .long("cache-dir")
+        .help("Sets the folder of intermediate files, which overrides the one in workspace.toml.")
+        .takes_value(true)
+}
+
+fn profile_arg() -> Arg<'static, 'static> {
+    Arg::with_name("profile")
+        .long("profile")
+        .help("Sets the build profile in workspace.toml, which overrides destination, platform, compression and check mode.")
+        .takes_value(true)
+}
+
+fn check_arg() -> Arg<'static, 'static> {
+    Arg::with_name("check")
+        .long("check")
+        .help("Sets the way to detect modified files, defaults to combined which hashes the contents only if timestamp changes.")
+        .possible_values(&["timestamp", "combined", "strict"])
+        .takes_value(true)
+}
+
+fn jobs_arg() -> Arg<'static, 'static> {
+    Arg::with_name("jobs")
+        .short("j")
+        .long("jobs")
+        .help("Sets the number of assets that are compiled concurrently, defaults to the number of logical CPUs.")
+        .takes_value(true)
+}
+
+fn keep_going_arg() -> Arg<'static, 'static> {
+    Arg::with_name("keep-going")
+        .short("k")
+        .long("keep-going")
+        .help("Keeps building the other assets when an asset fails, and reports all the failures at the end.")
+}
+
+fn reproducible_arg() -> Arg<'static, 'static> {
+    Arg::with_name("reproducible")
+        .long("reproducible")
+        .help("Derives the UUIDs of new assets and resources from their names, so clean builds produce identical resources.")
 }
 
 fn cmd_build<'a>(matches: &ArgMatches<'a>) -> Result<()> {
-    let path = match matches.value_of("path") {
+    let path = path(matches);
+    let mut ws = workspace::Workspace::new(&path, options(matches)?)?;
+
+    // The report is written even if the build failed, so the errors could be inspected.
+    let result = ws.import_all();
+    if let Some(v) = matches.value_of("report") {
+        ws.report().save(v)?;
+    }
+
+    result
+}
+
+fn cmd_watch<'a>(matches: &ArgMatches<'a>) -> Result<()> {
+    let path = path(matches);
+    let mut ws = workspace::Workspace::new(&path, options(matches)?)?;
+    ws.import_all()?;
+    ws.watch()
+}
+
+fn cmd_init<'a>(matches: &ArgMatches<'a>) -> Result<()> {
+    workspace::maintenance::init(path(matches), matches.is_present("force"))
+}
+
+fn cmd_check<'a>(matches: &ArgMatches<'a>) -> Result<()> {
+    workspace::maintenance::check(path(matches), &options(matches)?)
+}
+
+fn cmd_mv<'a>(matches: &ArgMatches<'a>) -> Result<()> {
+    workspace::maintenance::mv(
+        path(matches),
+        &options(matches)?,
+        matches.value_of("src").unwrap(),
+        matches.value_of("dst").unwrap(),
+    )
+}
+
+fn cmd_clean<'a>(matches: &ArgMatches<'a>) -> Result<()> {
+    workspace::maintenance::clean(
+        path(matches),
+        &options(matches)?,
+        matches.is_present("resources"),
+        matches.is_present("intermediates"),
+    )
+}
+
+fn cmd_cache<'a>(matches: &ArgMatches<'a>) -> Result<()> {
+    match matches.subcommand() {
+        ("stats", Some(matches)) => {
+            workspace::maintenance::cache_stats(path(matches), &options(matches)?)
+        }
+        ("prune", Some(matches)) => {
+            workspace::maintenance::cache_prune(path(matches), &options(matches)?)
+        }
+        ("verify", Some(matches)) => {
+            workspace::maintenance::cache_verify(path(matches), &options(matches)?)
+        }
+        _ => Ok(()),
+    }
+}
+
+fn path<'a>(matches: &ArgMatches<'a>) -> PathBuf {
+    match matches.value_of("path") {
         Some(v) => Path::new(v).into(),
         None => ::std::env::current_dir().unwrap(),
+    }
+}
+
+fn options<'a>(matches: &ArgMatches<'a>) -> Result<workspace::Options> {
+    let jobs = match matches.value_of("jobs") {
+        Some(v) => match v.parse() {
+            Ok(v) => v,
+            Err(_) => bail!("The number of jobs ({}) is not a valid integer.", v),
+        },
+        None => 0,
     };
 
-    let mut ws = workspace::Workspace::new(&path, platform::RuntimePlatform::Macos, true)?;
-    ws.import_all()?;
-    return Ok(());
+    let check = match matches.value_of("check") {
+        Some("timestamp") => CheckMode::Timestamp,
+        Some("combined") | None => CheckMode::Combined,
+        Some("strict") => CheckMode::Strict,
+        Some(v) => bail!("Unknown check mode {}.", v),
+    };
+
+    Ok(workspace::Options {
+        platform: platform::RuntimePlatform::Macos,
+        check: check,
+        jobs: jobs,
+        cache_dir: matches.value_of("cache-dir").map(PathBuf::from),
+        keep_going: matches.is_present("keep-going"),
+        compression: None,
+        reproducible: matches.is_present("reproducible"),
+        profile: matches.value_of("profile").map(|v| v.to_owned()),
+    })
 }
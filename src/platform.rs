@@ -7,6 +7,18 @@ pub enum RuntimePlatform {
     Android,
 }
 
+impl RuntimePlatform {
+    /// Gets all the platforms that resources could be built for.
+    pub fn all() -> [RuntimePlatform; 4] {
+        [
+            RuntimePlatform::Macos,
+            RuntimePlatform::Windows,
+            RuntimePlatform::Ios,
+            RuntimePlatform::Android,
+        ]
+    }
+}
+
 impl ::std::fmt::Display for RuntimePlatform {
     fn fmt(&self, f: &mut ::std::fmt::Formatter) -> ::std::fmt::Result {
         match *self {
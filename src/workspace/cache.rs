@@ -1,83 +1,251 @@
-use std::collections::hash_map::DefaultHasher;
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
-use std::hash::{Hash, Hasher};
+use std::hash::Hasher;
+use std::io::Read;
 use std::path::{Path, PathBuf};
 use std::time::UNIX_EPOCH;
 
-use crc::crc32;
 use toml;
+use twox_hash::XxHash;
 use uuid::Uuid;
 use walkdir::WalkDir;
 
 use super::metadata::AssetMetadata;
+use super::mounts::Mounts;
 use super::utils;
+use platform::RuntimePlatform;
 
 pub type Result<T> = ::std::result::Result<T, ::failure::Error>;
 pub const NAME: &str = "intermediates.toml";
 
+/// The version of cache format. Caches with different version are wiped out and rebuilt.
+pub const VERSION: u32 = 2;
+
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+struct AssetCacheManifest {
+    version: u32,
+    assets: HashMap<PathBuf, AssetCacheItem>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AssetCacheItem {
+    /// The fingerprint of importer version, platform and params that used in last import.
+    #[serde(default)]
+    pub fingerprint: String,
+    /// The platform that used in last import.
+    #[serde(default)]
+    pub platform: Option<RuntimePlatform>,
     pub file: Metadata,
     pub metafile: Metadata,
     pub resources: HashMap<PathBuf, Metadata>,
     pub intermediates: HashMap<PathBuf, Metadata>,
+    /// The other source files that read by importer, relative to assets folder.
+    #[serde(default)]
+    pub dependencies: HashMap<PathBuf, Metadata>,
+
+    /// Whether the outputs have been restored from shared cache in this build.
+    #[serde(skip)]
+    pub restored: bool,
+    /// The formats that chosen by importer in this build.
+    #[serde(skip)]
+    pub formats: Vec<String>,
+}
+
+impl AssetCacheItem {
+    pub fn new() -> Self {
+        AssetCacheItem {
+            fingerprint: String::new(),
+            platform: None,
+            file: Metadata::new(Uuid::new_v4()),
+            metafile: Metadata::new(Uuid::new_v4()),
+            resources: HashMap::new(),
+            intermediates: HashMap::new(),
+            dependencies: HashMap::new(),
+            restored: false,
+            formats: Vec::new(),
+        }
+    }
+
+    pub fn file(&mut self, writable: bool) -> Metadata {
+        if writable {
+            self.file.modified = true;
+        }
+
+        self.file.clone()
+    }
+
+    pub fn metafile(&mut self, writable: bool) -> Metadata {
+        if writable {
+            self.metafile.modified = true;
+        }
+
+        self.metafile.clone()
+    }
+
+    /// Checks if the asset file, meta-file or any of dependencies has been modified.
+    pub fn modified(&self) -> bool {
+        self.file.modified
+            || self.metafile.modified
+            || self.dependencies.values().any(|v| v.modified)
+    }
+
+    /// Registers a source file that read by importer, which is relative to assets folder.
+    pub fn dependency<T: AsRef<Path>>(&mut self, name: T) {
+        self.dependencies
+            .entry(name.as_ref().to_owned())
+            .or_insert_with(|| Metadata::new(Uuid::nil()));
+    }
+
+    pub fn intermediate<T: AsRef<Path>>(&mut self, name: T, writable: bool) -> Metadata {
+        let name = utils::canonicalize(name.as_ref());
+        let metadata = self
+            .intermediates
+            .entry(name)
+            .or_insert_with(|| Metadata::new(Uuid::new_v4()));
+
+        if writable {
+            metadata.modified = true;
+        }
+
+        metadata.clone()
+    }
+
+    pub fn resource<T: AsRef<Path>>(&mut self, name: T, writable: bool) -> Metadata {
+        let name = utils::canonicalize(name.as_ref());
+        let metadata = self
+            .resources
+            .entry(name)
+            .or_insert_with(|| Metadata::new(Uuid::new_v4()));
+
+        if writable {
+            metadata.modified = true;
+        }
+
+        metadata.clone()
+    }
+
+    fn check(&mut self, mounts: &Mounts, dir: &Path, filename: &Path, mode: CheckMode) -> Result<()> {
+        self.file.check_file(mounts, filename, mode)?;
+        self.metafile.check_metafile(mounts, filename, mode)?;
+
+        for (k, metadata) in &mut self.intermediates {
+            metadata.check_intermediate(dir, k, mode)?;
+        }
+
+        for (_, metadata) in &mut self.resources {
+            metadata.check_resource(dir, mode)?;
+        }
+
+        for (k, metadata) in &mut self.dependencies {
+            metadata.check_file(mounts, k, mode)?;
+        }
+
+        Ok(())
+    }
+}
+
+/// The statistics of cache.
+#[derive(Debug, Clone, Copy)]
+pub struct AssetCacheStats {
+    /// The number of cached assets.
+    pub assets: usize,
+    /// The number of files.
+    pub files: usize,
+    /// The total size of files in bytes.
+    pub bytes: u64,
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct AssetCache {
-    strict: bool,
-    assets_dir: PathBuf,
+    mode: CheckMode,
+    mounts: Mounts,
     dir: PathBuf,
     assets: HashMap<PathBuf, AssetCacheItem>,
 }
 
 impl AssetCache {
-    pub fn new(assets_dir: &Path, strict: bool) -> Result<AssetCache> {
-        let mut hasher = DefaultHasher::new();
-        assets_dir.hash(&mut hasher);
-
-        let dir = utils::current_exe_dir()
-            .join("intermediates")
-            .join(format!("{:X}/", hasher.finish()));
+    /// Opens the cache of assets folder, and re-checks all the files.
+    pub fn new(
+        cache_dir: &Path,
+        root: &Path,
+        mounts: &Mounts,
+        platform: RuntimePlatform,
+        mode: CheckMode,
+    ) -> Result<AssetCache> {
+        let mut cache = Self::open(cache_dir, root, mounts, platform, mode)?;
+        cache.scan()?;
+        Ok(cache)
+    }
 
+    /// Opens the cache of assets folder under `cache_dir`, which is built for `platform`.
+    pub fn open(
+        cache_dir: &Path,
+        root: &Path,
+        mounts: &Mounts,
+        platform: RuntimePlatform,
+        mode: CheckMode,
+    ) -> Result<AssetCache> {
+        let dir = locate(cache_dir, root, mounts, platform);
         if !dir.exists() {
             fs::create_dir_all(&dir)?;
         }
 
-        let manifest = dir.join(NAME);
-        let assets = fs::read(&manifest)
+        let manifest: Option<AssetCacheManifest> = fs::read(dir.join(NAME))
             .ok()
-            .and_then(|v| toml::de::from_slice(&v).ok())
-            .unwrap_or_default();
+            .and_then(|v| toml::de::from_slice(&v).ok());
+
+        let assets = match manifest {
+            Some(ref v) if v.version == VERSION => v.assets.clone(),
+            _ => {
+                // Wipes out the intermediates that might be generated in other formats.
+                if dir.join(NAME).exists() {
+                    info!("Cache format has been changed, rebuilds all the intermediates.");
+                }
+
+                fs::remove_dir_all(&dir)?;
+                fs::create_dir_all(&dir)?;
+                HashMap::new()
+            }
+        };
 
         info!("Generates cahce of intermediate files at {:?}.", dir);
 
-        let mut cache = AssetCache {
-            assets_dir: assets_dir.to_owned(),
+        Ok(AssetCache {
+            mounts: mounts.clone(),
             dir: dir,
             assets: assets,
-            strict: strict,
-        };
-
-        cache.scan()?;
-        Ok(cache)
+            mode: mode,
+        })
     }
 
-    // Imports files.
-    fn scan(&mut self) -> Result<()> {
+    /// Re-checks all the files, which might be modified after this cache being created.
+    pub fn scan(&mut self) -> Result<()> {
         for (n, v) in &mut self.assets {
-            v.file.check_file(&self.assets_dir, n, self.strict)?;
-            v.metafile
-                .check_metafile(&self.assets_dir, n, self.strict)?;
+            v.check(&self.mounts, &self.dir, n, self.mode)?;
+        }
 
-            for (k, metadata) in &mut v.intermediates {
-                metadata.check_intermediate(&self.dir, k, self.strict)?;
-            }
+        Ok(())
+    }
 
-            for (_, metadata) in &mut v.resources {
-                metadata.check_resource(&self.dir, self.strict)?;
-            }
+    /// Re-checks the files of asset `filename`.
+    pub fn refresh<T: AsRef<Path>>(&mut self, filename: T) -> Result<()> {
+        let filename = filename.as_ref();
+        if let Some(v) = self.assets.get_mut(filename) {
+            v.check(&self.mounts, &self.dir, filename, self.mode)?;
+        }
+
+        Ok(())
+    }
+
+    /// Marks the asset file and meta-file of `filename` as up to date, which is used after
+    /// they are moved or renamed without changing the contents of asset.
+    pub fn touch<T: AsRef<Path>>(&mut self, filename: T) -> Result<()> {
+        let filename = filename.as_ref();
+        if let Some(v) = self.assets.get_mut(filename) {
+            v.file.check_file(&self.mounts, filename, self.mode)?;
+            v.metafile.check_metafile(&self.mounts, filename, self.mode)?;
+            v.file.modified = false;
+            v.metafile.modified = false;
         }
 
         Ok(())
@@ -103,14 +271,18 @@ impl AssetCache {
             }
         });
 
-        //
+        self.strip_files()
+    }
+
+    /// Removes the files that are not referenced by any entry.
+    fn strip_files(&self) -> Result<()> {
         let mut intermediates = HashSet::new();
-        for (_, v) in &mut self.assets {
-            for (k, metadata) in &mut v.intermediates {
+        for v in self.assets.values() {
+            for (k, metadata) in &v.intermediates {
                 intermediates.insert(metadata.path_with_extension(&self.dir, k));
             }
 
-            for (_, metadata) in &mut v.resources {
+            for metadata in v.resources.values() {
                 intermediates.insert(metadata.path(&self.dir));
             }
         }
@@ -136,111 +308,264 @@ impl AssetCache {
     pub fn save(&mut self) -> Result<()> {
         for (n, v) in &mut self.assets {
             if v.file.modified {
-                v.file.check_file(&self.assets_dir, n, self.strict)?;
+                v.file.check_file(&self.mounts, n, self.mode)?;
             }
 
             if v.metafile.modified {
                 v.metafile
-                    .check_metafile(&self.assets_dir, n, self.strict)?;
+                    .check_metafile(&self.mounts, n, self.mode)?;
             }
 
             for (k, metadata) in &mut v.intermediates {
                 if metadata.modified {
-                    metadata.check_intermediate(&self.dir, k, self.strict)?;
+                    metadata.check_intermediate(&self.dir, k, self.mode)?;
                 }
             }
 
             for (_, metadata) in &mut v.resources {
                 if metadata.modified {
-                    metadata.check_resource(&self.dir, self.strict)?;
+                    metadata.check_resource(&self.dir, self.mode)?;
+                }
+            }
+
+            for (k, metadata) in &mut v.dependencies {
+                if metadata.modified {
+                    metadata.check_file(&self.mounts, k, self.mode)?;
                 }
             }
         }
 
-        let contents = toml::ser::to_string_pretty(&self.assets)?;
+        let manifest = AssetCacheManifest {
+            version: VERSION,
+            assets: self.assets.clone(),
+        };
+
+        let contents = toml::ser::to_string_pretty(&manifest)?;
         let manifest = self.dir.join(NAME);
         fs::write(manifest, contents)?;
         Ok(())
     }
 
-    #[inline]
-    pub fn dir(&self) -> &Path {
-        &self.dir
-    }
+    /// Gets the assets that depend on `names` directly or indirectly, excluding `names`.
+    pub fn dependents(&self, names: &BTreeSet<PathBuf>) -> BTreeSet<PathBuf> {
+        let mut dependents = BTreeSet::new();
+        let mut changed: Vec<_> = names.iter().cloned().collect();
 
-    pub fn item<T: AsRef<Path>>(&mut self, filename: T) -> &mut AssetCacheItem {
-        self.assets
-            .entry(filename.as_ref().to_owned())
-            .or_insert_with(|| AssetCacheItem {
-                file: Metadata::new(Uuid::new_v4()),
-                metafile: Metadata::new(Uuid::new_v4()),
-                resources: HashMap::new(),
-                intermediates: HashMap::new(),
-            })
+        while let Some(v) = changed.pop() {
+            for (k, item) in &self.assets {
+                if dependents.contains(k) || names.contains(k) {
+                    continue;
+                }
+
+                if item.dependencies.keys().any(|d| d.starts_with(&v)) {
+                    dependents.insert(k.clone());
+                    changed.push(k.clone());
+                }
+            }
+        }
+
+        dependents
     }
 
-    pub fn file<T: AsRef<Path>>(&mut self, filename: T, writable: bool) -> Metadata {
-        let item = self.item(filename);
-        if writable {
-            item.file.modified = true;
+    /// Sorts assets into levels, where each asset only depends on the assets in previous
+    /// levels. So the assets in the same level could be built concurrently.
+    pub fn levels(&self, names: Vec<PathBuf>) -> Vec<Vec<PathBuf>> {
+        let set: HashSet<_> = names.iter().cloned().collect();
+        let mut pending: BTreeMap<PathBuf, HashSet<PathBuf>> = names
+            .into_iter()
+            .map(|v| {
+                let dependencies = self
+                    .assets
+                    .get(&v)
+                    .map(|item| {
+                        item.dependencies
+                            .keys()
+                            .filter(|d| set.contains(*d) && **d != v)
+                            .cloned()
+                            .collect()
+                    }).unwrap_or_default();
+
+                (v, dependencies)
+            }).collect();
+
+        let mut levels = Vec::new();
+        while !pending.is_empty() {
+            let level: Vec<_> = pending
+                .iter()
+                .filter(|&(_, v)| v.is_empty())
+                .map(|(k, _)| k.clone())
+                .collect();
+
+            if level.is_empty() {
+                warn!(
+                    "Found circular dependencies between {:?}.",
+                    pending.keys().collect::<Vec<_>>()
+                );
+
+                levels.push(pending.keys().cloned().collect());
+                break;
+            }
+
+            for v in &level {
+                pending.remove(v);
+            }
+
+            for dependencies in pending.values_mut() {
+                for v in &level {
+                    dependencies.remove(v);
+                }
+            }
+
+            levels.push(level);
         }
 
-        item.file
+        levels
     }
 
-    pub fn metafile<T: AsRef<Path>>(&mut self, filename: T, writable: bool) -> Metadata {
-        let item = self.item(filename);
-        if writable {
-            item.metafile.modified = true;
+    /// Gets the statistics of this cache.
+    pub fn stats(&self) -> Result<AssetCacheStats> {
+        let mut stats = AssetCacheStats {
+            assets: self.assets.len(),
+            files: 0,
+            bytes: 0,
+        };
+
+        for e in WalkDir::new(&self.dir).into_iter().filter_map(|e| e.ok()) {
+            if e.file_type().is_file() {
+                stats.files += 1;
+                stats.bytes += e.metadata()?.len();
+            }
         }
 
-        item.metafile
+        Ok(stats)
     }
 
-    pub fn intermediate<T1, T2>(&mut self, filename: T1, name: T2, writable: bool) -> Metadata
-    where
-        T1: AsRef<Path>,
-        T2: AsRef<Path>,
-    {
-        let name = utils::canonicalize(name.as_ref());
-        let item = self.item(filename);
-        let metadata = item
-            .intermediates
-            .entry(name)
-            .or_insert_with(|| Metadata::new(Uuid::new_v4()));
+    /// Drops the entries that are built for other platforms, and the files that are not
+    /// referenced by any entry. Returns the number of dropped entries.
+    pub fn prune(&mut self, platform: RuntimePlatform) -> Result<usize> {
+        let len = self.assets.len();
+        self.assets
+            .retain(|_, v| v.platform.map(|p| p == platform).unwrap_or(true));
 
-        if writable {
-            metadata.modified = true;
+        self.strip_files()?;
+        Ok(len - self.assets.len())
+    }
+
+    /// Verifies the checksums of intermediates and resources, and drops the entries that are
+    /// missing or corrupted, so they will be rebuilt next time. Returns the names of dropped
+    /// entries.
+    pub fn verify(&mut self) -> Result<Vec<PathBuf>> {
+        let mut corrupted = Vec::new();
+        for (n, v) in &self.assets {
+            let mut ok = true;
+
+            // Entries that are checked by timestamp only have no hash to verify against, so
+            // only their existence is verified.
+            for (k, metadata) in &v.intermediates {
+                let mut checked = metadata.clone();
+                checked.check_intermediate(&self.dir, k, CheckMode::Strict)?;
+                ok = ok
+                    && metadata.path_with_extension(&self.dir, k).exists()
+                    && (metadata.hash.is_empty() || !checked.modified);
+            }
+
+            for metadata in v.resources.values() {
+                let mut checked = metadata.clone();
+                checked.check_resource(&self.dir, CheckMode::Strict)?;
+                ok = ok
+                    && metadata.path(&self.dir).exists()
+                    && (metadata.hash.is_empty() || !checked.modified);
+            }
+
+            if !ok {
+                corrupted.push(n.clone());
+            }
+        }
+
+        corrupted.sort();
+        for v in &corrupted {
+            self.assets.remove(v);
         }
 
-        *metadata
+        self.strip_files()?;
+        Ok(corrupted)
     }
 
-    pub fn resource<T1, T2>(&mut self, filename: T1, name: T2, writable: bool) -> Metadata
-    where
-        T1: AsRef<Path>,
-        T2: AsRef<Path>,
-    {
-        let name = utils::canonicalize(name.as_ref());
-        let item = self.item(filename);
-        let metadata = item
-            .resources
-            .entry(name)
-            .or_insert_with(|| Metadata::new(Uuid::new_v4()));
+    #[inline]
+    pub fn dir(&self) -> &Path {
+        &self.dir
+    }
 
-        if writable {
-            metadata.modified = true;
+    #[inline]
+    pub fn get<T: AsRef<Path>>(&self, filename: T) -> Option<&AssetCacheItem> {
+        self.assets.get(filename.as_ref())
+    }
+
+    pub fn item<T: AsRef<Path>>(&mut self, filename: T) -> &mut AssetCacheItem {
+        self.assets
+            .entry(filename.as_ref().to_owned())
+            .or_insert_with(AssetCacheItem::new)
+    }
+
+    /// Takes the cache item of asset out, so it could be updated by another thread without
+    /// locking the whole cache. It should be put back with `put` afterwards.
+    pub fn take<T: AsRef<Path>>(&mut self, filename: T) -> AssetCacheItem {
+        self.assets
+            .remove(filename.as_ref())
+            .unwrap_or_else(AssetCacheItem::new)
+    }
+
+    /// Puts the cache item of asset back.
+    pub fn put<T: AsRef<Path>>(&mut self, filename: T, item: AssetCacheItem) {
+        self.assets.insert(filename.as_ref().to_owned(), item);
+    }
+
+    /// Moves the entry of asset `from` to `to` after the asset file being moved, so its
+    /// intermediates are reused. The resource that named after the asset is renamed too.
+    pub fn rename<T1: AsRef<Path>, T2: AsRef<Path>>(&mut self, from: T1, to: T2) {
+        let (from, to) = (from.as_ref(), to.as_ref());
+        if let Some(mut item) = self.assets.remove(from) {
+            if let Some(v) = item.resources.remove(from) {
+                item.resources.insert(to.to_owned(), v);
+            }
+
+            // Re-checks the files at new location while saving.
+            item.file.modified = true;
+            item.metafile.modified = true;
+            self.assets.insert(to.to_owned(), item);
         }
+    }
+}
+
+/// The way to detect modifications of files.
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckMode {
+    /// Compares the size and modified time only, which never reads the contents of files.
+    Timestamp,
+    /// Compares the content hash only if the size or modified time has been changed.
+    Combined,
+    /// Always compares the content hash.
+    Strict,
+}
 
-        *metadata
+impl Default for CheckMode {
+    fn default() -> Self {
+        CheckMode::Combined
     }
 }
 
-#[derive(Serialize, Deserialize, Debug, Clone, Copy)]
+#[derive(Serialize, Deserialize, Debug, Clone)]
 pub struct Metadata {
     pub uuid: Uuid,
-    pub checksum: u32,
-    pub last_modified_time: u32,
+    /// The 128-bit xxHash of contents in hex, which is empty if not computed yet.
+    #[serde(default)]
+    pub hash: String,
+    #[serde(default)]
+    pub size: u64,
+    #[serde(default)]
+    pub mtime: u64,
+    #[serde(default)]
+    pub mtime_nanos: u32,
 
     #[serde(skip)]
     pub modified: bool,
@@ -250,20 +575,22 @@ impl Metadata {
     pub fn new(uuid: Uuid) -> Self {
         Metadata {
             uuid: uuid,
-            checksum: 0,
-            last_modified_time: 0,
+            hash: String::new(),
+            size: 0,
+            mtime: 0,
+            mtime_nanos: 0,
             modified: true,
         }
     }
 
-    pub fn check_intermediate(&mut self, dir: &Path, k: &Path, strict: bool) -> Result<()> {
+    pub fn check_intermediate(&mut self, dir: &Path, k: &Path, mode: CheckMode) -> Result<()> {
         let path = self.path_with_extension(dir, k);
-        self.check(&path, strict)
+        self.check(&path, mode)
     }
 
-    pub fn check_resource(&mut self, dir: &Path, strict: bool) -> Result<()> {
+    pub fn check_resource(&mut self, dir: &Path, mode: CheckMode) -> Result<()> {
         let path = self.path(dir);
-        self.check(&path, strict)
+        self.check(&path, mode)
     }
 
     pub fn path(&self, dir: &Path) -> PathBuf {
@@ -278,37 +605,108 @@ impl Metadata {
         }
     }
 
-    pub fn check_file(&mut self, dir: &Path, filename: &Path, strict: bool) -> Result<()> {
-        let path = dir.join(filename);
-        self.check(&path, strict)
+    pub fn check_file(&mut self, mounts: &Mounts, filename: &Path, mode: CheckMode) -> Result<()> {
+        let path = mounts.join(filename);
+        self.check(&path, mode)
     }
 
-    pub fn check_metafile(&mut self, dir: &Path, filename: &Path, strict: bool) -> Result<()> {
-        let path = AssetMetadata::with_extension(&dir.join(filename));
-        self.check(&path, strict)
+    pub fn check_metafile(&mut self, mounts: &Mounts, filename: &Path, mode: CheckMode) -> Result<()> {
+        let path = AssetMetadata::with_extension(&mounts.join(filename));
+        self.check(&path, mode)
     }
 
-    fn check(&mut self, path: &Path, strict: bool) -> Result<()> {
-        if path.exists() {
-            let file_md = fs::metadata(&path).unwrap();
-            let wtime = file_md.modified().unwrap();
-            let wtime = wtime.duration_since(UNIX_EPOCH).unwrap().as_secs() as u32;
+    fn check(&mut self, path: &Path, mode: CheckMode) -> Result<()> {
+        let file_md = match fs::metadata(path) {
+            Ok(v) => v,
+            Err(_) => {
+                // Keeps the hash, so the file could be found by its contents if it was moved.
+                self.size = 0;
+                self.mtime = 0;
+                self.mtime_nanos = 0;
+                self.modified = true;
+                return Ok(());
+            }
+        };
+
+        let wtime = file_md.modified()?.duration_since(UNIX_EPOCH)?;
+        let touched = file_md.len() != self.size
+            || wtime.as_secs() != self.mtime
+            || wtime.subsec_nanos() != self.mtime_nanos;
 
-            if strict {
-                let crc = crc32::checksum_ieee(&fs::read(&path).unwrap());
-                self.modified = crc != self.checksum;
-                self.checksum = crc;
-            } else {
-                self.modified = self.last_modified_time != wtime;
+        self.modified = match mode {
+            CheckMode::Timestamp => {
+                // The hash could not be trusted anymore since we never read the contents.
+                if touched {
+                    self.hash.clear();
+                }
+
+                touched
+            }
+            CheckMode::Combined if !touched && !self.hash.is_empty() => false,
+            _ => {
+                let hash = hash(path)?;
+                let modified = hash != self.hash;
+                self.hash = hash;
+                modified
             }
+        };
 
-            self.last_modified_time = wtime;
-        } else {
-            self.checksum = 0;
-            self.last_modified_time = 0;
-            self.modified = true;
+        self.size = file_md.len();
+        self.mtime = wtime.as_secs();
+        self.mtime_nanos = wtime.subsec_nanos();
+        Ok(())
+    }
+}
+
+/// Gets the folder of cache under `cache_dir`. The cache is keyed by the path of main assets
+/// folder relative to workspace `root`, so it survives moving the whole workspace around. If
+/// `cache_dir` is outside of workspace, the absolute path is used instead to avoid collisions
+/// between workspaces. Each platform has its own cache, so switching between profiles never
+/// rebuilds the assets that are built for the other platform.
+pub fn locate(cache_dir: &Path, root: &Path, mounts: &Mounts, platform: RuntimePlatform) -> PathBuf {
+    let assets_dir = mounts.main();
+    let key = if cache_dir.starts_with(root) {
+        assets_dir.strip_prefix(root).unwrap_or(assets_dir)
+    } else {
+        assets_dir
+    };
+
+    // Uses a stable hash with separators normalized, so the key never changes between builds
+    // of crayon-cli or host platforms.
+    let mut hasher = XxHash::with_seed(0);
+    for v in key.components() {
+        hasher.write(v.as_os_str().to_string_lossy().as_bytes());
+        hasher.write(b"/");
+    }
+
+    hasher.write(format!("{:?}", platform).as_bytes());
+    cache_dir.join(format!("{:016X}/", hasher.finish()))
+}
+
+/// Checks if `path` is the cache of a workspace, which is named after a 16-digit hex key and
+/// contains a manifest.
+pub fn is_cache_dir(path: &Path) -> bool {
+    let key = path.file_name().and_then(|v| v.to_str()).unwrap_or("");
+    key.len() == 16 && key.chars().all(|c| c.is_ascii_hexdigit()) && path.join(NAME).is_file()
+}
+
+/// Computes the 128-bit xxHash of file contents, which is composed of two 64-bit hashes with
+/// different seeds.
+pub fn hash(path: &Path) -> Result<String> {
+    let mut file = fs::File::open(path)?;
+    let mut hashers = [XxHash::with_seed(0), XxHash::with_seed(1)];
+    let mut buf = vec![0; 64 * 1024];
+
+    loop {
+        let len = file.read(&mut buf)?;
+        if len == 0 {
+            break;
         }
 
-        Ok(())
+        for v in &mut hashers {
+            v.write(&buf[..len]);
+        }
     }
+
+    Ok(format!("{:016X}{:016X}", hashers[0].finish(), hashers[1].finish()))
 }
@@ -1,68 +1,102 @@
-use std::collections::{BTreeMap, HashMap, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashMap, HashSet};
 use std::fs;
+use std::hash::Hasher;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Instant, UNIX_EPOCH};
 
 use crayon::bincode;
 use crayon::res::manifest::{self, Manifest, ManifestItem};
 use failure::ResultExt;
+use rayon::prelude::*;
+use rayon::{ThreadPool, ThreadPoolBuilder};
+use serde_json;
+use toml;
+use twox_hash::XxHash;
 use uuid::Uuid;
 use walkdir::WalkDir;
 
-use super::cache::AssetCache;
+use super::cache::{self, AssetCache, AssetCacheItem};
 use super::metadata::{AssetMetadata, ResourceMetadata, EXTENSION};
-use super::settings::AssetSettings;
+use super::pattern::Pattern;
+use super::report::{AssetReport, AssetStatus, BuildReport};
+use super::settings::{AssetSettings, DefaultParams};
+use super::storage::{Bundle, Storage};
+use super::utils;
+use super::{Mounts, Options};
 
 use assets::*;
-use platform::RuntimePlatform;
+use logs;
+use platform::{Compression, RuntimePlatform};
 
 pub type Result<T> = ::std::result::Result<T, ::failure::Error>;
 
+/// The folder under workspace that the meta-files of missing assets are moved into.
+pub const TRASH: &str = ".trash";
+
 pub struct AssetDatabase {
-    assets_dir: PathBuf,
+    mounts: Mounts,
     resources_dir: PathBuf,
+    trash_dir: PathBuf,
     platform: RuntimePlatform,
+    compression: Option<Compression>,
     cache: AssetCache,
     assets: BTreeMap<PathBuf, AssetMetadata>,
     exts: HashMap<String, AssetType>,
     importers: HashMap<AssetType, Box<AssetImporter>>,
+    defaults: Vec<(Pattern, DefaultParams)>,
+    storage: Option<Box<Storage>>,
+    pool: ThreadPool,
+    keep_going: bool,
+    reproducible: bool,
+    report: BTreeMap<PathBuf, AssetReport>,
 }
 
 impl AssetDatabase {
     pub fn new<T: AsRef<Path>>(
         root: T,
         params: AssetSettings,
-        platform: RuntimePlatform,
-        strict: bool,
+        cache_dir: &Path,
+        storage: Option<Box<Storage>>,
+        options: &Options,
     ) -> Result<AssetDatabase> {
         let root = root.as_ref();
 
         // Makes sure that assets and resources folder exist.
-        let assets_dir = root.join(&params.source);
+        let mounts = Mounts::new(root, &params)?;
         let resources_dir = root.join(&params.destination);
 
-        if resources_dir.starts_with(&assets_dir) || assets_dir.starts_with(&resources_dir) {
-            bail!("The assets folder can not be located under resources folder.");
-        }
-
-        if !assets_dir.exists() {
-            bail!("The assets folder ({:?}) is not exists.", assets_dir);
-        }
-
         if !resources_dir.exists() {
             fs::create_dir_all(&resources_dir)
                 .context("Failed to create resource folder at destination")?;
         }
 
-        let cache = AssetCache::new(&assets_dir, strict)?;
+        let pool = ThreadPoolBuilder::new()
+            .num_threads(options.jobs)
+            .build()?;
+
+        let cache = AssetCache::new(cache_dir, root, &mounts, options.platform, options.check)?;
         let mut database = AssetDatabase {
-            assets_dir: assets_dir,
+            mounts: mounts,
             resources_dir: resources_dir,
-            platform: platform,
+            trash_dir: root.join(TRASH),
+            platform: options.platform,
+            compression: options.compression,
             cache: cache,
             assets: BTreeMap::new(),
             exts: HashMap::new(),
             importers: HashMap::new(),
+            defaults: params
+                .defaults
+                .iter()
+                .map(|v| (Pattern::new(&v.pattern), v.clone()))
+                .collect(),
+            storage: storage,
+            pool: pool,
+            keep_going: options.keep_going,
+            reproducible: options.reproducible,
+            report: BTreeMap::new(),
         };
 
         let ti = TextureImporter {};
@@ -90,145 +124,994 @@ impl AssetDatabase {
             }
         }
 
-        database.scan()?;
         Ok(database)
     }
 
+    /// Scans the assets folder, and imports all the modified assets.
     pub fn import_all(&mut self) -> Result<()> {
-        fs::remove_dir_all(&self.resources_dir)?;
-        fs::create_dir_all(&self.resources_dir)?;
+        self.report.clear();
+        self.scan()?;
 
-        let mut manifest = Manifest::new();
-        for (k, v) in &self.assets {
-            if let Some(i) = Self::importer(&self.exts, &self.importers, &k) {
+        let names: Vec<_> = self.assets.keys().cloned().collect();
+        self.import_assets(names)?;
+
+        self.strip_resources()?;
+        self.save_manifest()?;
+        self.cache.save()?;
+        self.failures()
+    }
+
+    /// Re-imports the assets under `changed` names after they have been created, modified or
+    /// removed, and updates the manifest in place.
+    pub fn refresh(&mut self, changed: &BTreeSet<PathBuf>) -> Result<()> {
+        self.report.clear();
+
+        let mut names = BTreeSet::new();
+        for name in changed {
+            for k in self.assets.keys() {
+                if k.starts_with(name) {
+                    names.insert(k.clone());
+                }
+            }
+
+            let path = self.mounts.join(name);
+            if path.is_file() {
+                if !self.mounts.excluded(name) {
+                    names.insert(name.to_owned());
+                }
+            } else if path.is_dir() {
+                for e in WalkDir::new(&path).into_iter().filter_map(|e| e.ok()) {
+                    if let Some(v) = self.asset_name(e.path()) {
+                        if e.file_type().is_file() {
+                            names.insert(v);
+                        }
+                    }
+                }
+            }
+        }
+
+        // Rebuilds the assets that depend on the changed files too.
+        let mut changed = changed.clone();
+        changed.extend(names.iter().cloned());
+        names.extend(self.cache.dependents(&changed));
+
+        let (mut files, missing): (Vec<_>, Vec<_>) = names
+            .into_iter()
+            .partition(|v| self.mounts.join(v).is_file());
+
+        // The assets might be moved without their meta-files.
+        self.recover(&missing, &files)?;
+
+        for v in &missing {
+            self.unload(v)?;
+        }
+
+        // The assets might be copied with their meta-files, and the older ones that share
+        // UUIDs with them have to be re-imported too.
+        for v in self.dedup(&files)? {
+            if !files.contains(&v) {
+                files.push(v);
+            }
+        }
+
+        for v in &files {
+            self.cache.refresh(v)?;
+        }
+
+        self.load_assets(files.clone())?;
+        self.import_assets(files)?;
+
+        self.cache.strip(&self.assets)?;
+        self.strip_resources()?;
+        self.save_manifest()?;
+        self.cache.save()?;
+        self.failures()
+    }
+
+    /// Rescans the whole assets folder and re-imports all the modified assets.
+    pub fn rescan(&mut self) -> Result<()> {
+        self.cache.scan()?;
+        self.import_all()
+    }
+
+    /// Gets the report of last build.
+    pub fn report(&self) -> BuildReport {
+        BuildReport::new(format!("{:?}", self.platform), &self.report)
+    }
+
+    /// Gets the assets folders.
+    pub fn mounts(&self) -> &Mounts {
+        &self.mounts
+    }
+
+    /// Reads the `.crayonignore` files of assets folders again, which should be followed by
+    /// a rescan.
+    pub fn reload_ignores(&mut self) {
+        self.mounts.reload();
+    }
+
+    /// Gets the name of asset that associated with file at `path`. Both the asset file and
+    /// its meta-file are mapped to the same name.
+    pub fn asset_name<T: AsRef<Path>>(&self, path: T) -> Option<PathBuf> {
+        let relative = self.mounts.name(path)?;
+        if relative.as_os_str().is_empty() {
+            return None;
+        }
+
+        for v in relative.components() {
+            if v.as_os_str().to_str().map(|s| s.starts_with(".")).unwrap_or(true) {
+                return None;
+            }
+        }
+
+        let name: PathBuf = relative.to_str()?.trim_right_matches(EXTENSION).into();
+        if self.mounts.excluded(&name) {
+            return None;
+        }
+
+        Some(name)
+    }
+
+    /// Imports the resources of assets concurrently.
+    fn import_assets(&mut self, names: Vec<PathBuf>) -> Result<()> {
+        for level in self.cache.levels(names) {
+            for (name, result) in self.run(level, |db, name, item| db.import(name, item)) {
+                if let Err(err) = result {
+                    if !self.keep_going {
+                        bail!("Failed to import {}. {}", name.display(), err);
+                    }
+
+                    self.fail(&name);
+                    continue;
+                }
+
+                let formats = ::std::mem::replace(&mut self.cache.item(&name).formats, Vec::new());
+                let output_size = self.assets.get(&name).map_or(0, |metadata| {
+                    metadata
+                        .resources
+                        .iter()
+                        .filter_map(|r| {
+                            let path = self.resources_dir.join(format!("{:X}", r.uuid.to_simple()));
+                            fs::metadata(path).ok()
+                        }).map(|v| v.len())
+                        .sum()
+                });
+
+                let report = self.report_of(&name);
+                report.formats = formats;
+                report.output_size = output_size;
+            }
+        }
+
+        Ok(())
+    }
+
+    fn import(&self, name: &Path, item: &mut AssetCacheItem) -> Result<()> {
+        let metadata = match self.assets.get(name) {
+            Some(v) => v,
+            None => return Ok(()),
+        };
+
+        // The previous resources of failed asset are kept as they are.
+        if self.failed(name) {
+            return Ok(());
+        }
+
+        if !item.restored {
+            if let Some(i) = Self::importer(&self.exts, &self.importers, name) {
                 let mut db = AssetIntermediateGenerator {
-                    assets_dir: &self.assets_dir,
-                    cache: &mut self.cache,
-                    metadata: v,
+                    mounts: &self.mounts,
+                    dir: self.cache.dir(),
+                    cache: item,
+                    metadata: metadata,
                     platform: self.platform,
+                    compression: self.compression,
                 };
 
                 i.import(&mut db)?;
             }
+        }
 
-            for r in &v.resources {
-                let cmd = self.cache.resource(k, &r.name, false);
-                let src = cmd.path(self.cache.dir());
+        for r in &metadata.resources {
+            let src = item.resource(&r.name, false).path(self.cache.dir());
+            let dst = self.resources_dir.join(format!("{:X}", r.uuid.to_simple()));
+
+            if src.exists() {
+                // Copies only the changed resources, so the running games could keep reading
+                // the others while building.
+                if Self::outdated(&src, &dst)? {
+                    utils::copy_atomically(&src, &dst)?;
+                }
+            } else {
+                warn!(
+                    "Resource {} of {} has not been generated.",
+                    r.name.display(),
+                    name.display()
+                );
+            }
+        }
+
+        if !item.restored && item.modified() {
+            self.store(name, metadata, item);
+        }
+
+        item.fingerprint = self.fingerprint(name, metadata)?;
+        item.platform = Some(self.platform);
+        Ok(())
+    }
+
+    /// Gets the key of the dependency list of asset in shared cache, which is a hash of source
+    /// file, metadata and fingerprint. The dependencies are only known after compiling, so
+    /// they are looked up with this key first.
+    fn key(&self, name: &Path, metadata: &AssetMetadata) -> Result<String> {
+        let source = fs::read(self.mounts.join(name))?;
+        let metadata_json = serde_json::to_string(metadata)?;
+        let fingerprint = self.fingerprint(name, metadata)?;
+
+        Ok(Self::digest(&[
+            &source,
+            metadata_json.as_bytes(),
+            fingerprint.as_bytes(),
+        ]))
+    }
+
+    /// Gets the key of the outputs of asset in shared cache, which is a hash of the key of
+    /// dependency list and the contents of dependencies.
+    fn bundle_key(&self, key: &str, dependencies: &[PathBuf]) -> String {
+        let mut sources = vec![key.as_bytes().to_vec()];
+        for v in dependencies {
+            sources.push(v.to_string_lossy().into_owned().into_bytes());
+            sources.push(fs::read(self.mounts.join(v)).unwrap_or_default());
+        }
+
+        let sources: Vec<_> = sources.iter().map(|v| v.as_slice()).collect();
+        Self::digest(&sources)
+    }
+
+    /// Computes the 128-bit xxHash of `sources`.
+    fn digest(sources: &[&[u8]]) -> String {
+        let mut key = String::new();
+        for seed in 0..2 {
+            let mut hasher = XxHash::with_seed(seed);
+            for v in sources {
+                hasher.write(v);
+            }
+
+            key.push_str(&format!("{:016X}", hasher.finish()));
+        }
+
+        key
+    }
+
+    /// Restores the intermediates and resources of asset from shared cache. Returns false if
+    /// it has never been built before, or the shared cache is unavailable.
+    fn restore(&self, name: &Path, metadata: &AssetMetadata, item: &mut AssetCacheItem) -> bool {
+        let storage = match self.storage {
+            Some(ref v) => v,
+            None => return false,
+        };
+
+        let result = self.key(name, metadata).and_then(|key| {
+            let dependencies: Vec<PathBuf> = match storage.get(&key)? {
+                Some(v) => bincode::deserialize(&v)?,
+                None => return Ok(false),
+            };
+
+            // Misses if any of the dependencies is different from the one that was built.
+            let bytes = match storage.get(&self.bundle_key(&key, &dependencies))? {
+                Some(v) => v,
+                None => return Ok(false),
+            };
+
+            let bundle: Bundle = bincode::deserialize(&bytes)?;
+
+            // Rejects the bundle before anything is written, if any of its entries points
+            // outside of the cache or assets folder.
+            let entries = bundle.intermediates.iter().chain(&bundle.resources).map(|v| &v.0);
+            for k in entries.chain(&dependencies) {
+                match utils::normalize(k) {
+                    Some(ref v) if v == k && !v.as_os_str().is_empty() => {}
+                    _ => bail!("Malformed entry {} in shared cache.", k.display()),
+                }
+            }
+
+            for (k, v) in bundle.intermediates {
+                let path = item.intermediate(&k, true).path_with_extension(self.cache.dir(), &k);
+                fs::write(path, v)?;
+            }
+
+            for (k, v) in bundle.resources {
+                let path = item.resource(&k, true).path(self.cache.dir());
+                fs::write(path, v)?;
+            }
+
+            // Registers the dependencies, so changing them rebuilds this asset later.
+            item.dependencies.clear();
+            for v in &dependencies {
+                item.dependency(v);
+            }
+
+            Ok(true)
+        });
+
+        match result {
+            Ok(true) => {
+                info!("Restores {} from shared cache.", name.display());
+                true
+            }
+            Ok(false) => false,
+            Err(err) => {
+                warn!("Failed to restore {} from shared cache. {}", name.display(), err);
+                false
+            }
+        }
+    }
+
+    /// Puts the intermediates and resources of asset into shared cache.
+    fn store(&self, name: &Path, metadata: &AssetMetadata, item: &AssetCacheItem) {
+        let storage = match self.storage {
+            Some(ref v) => v,
+            None => return,
+        };
+
+        let result = self.key(name, metadata).and_then(|key| {
+            let mut dependencies: Vec<_> = item.dependencies.keys().cloned().collect();
+            dependencies.sort();
+
+            let mut bundle = Bundle::default();
+            for (k, v) in &item.intermediates {
+                let path = v.path_with_extension(self.cache.dir(), k);
+                if path.exists() {
+                    bundle.intermediates.push((k.clone(), fs::read(path)?));
+                }
+            }
+
+            for (k, v) in &item.resources {
+                let path = v.path(self.cache.dir());
+                if path.exists() {
+                    bundle.resources.push((k.clone(), fs::read(path)?));
+                }
+            }
+
+            bundle.intermediates.sort_by(|lhs, rhs| lhs.0.cmp(&rhs.0));
+            bundle.resources.sort_by(|lhs, rhs| lhs.0.cmp(&rhs.0));
+
+            // Puts the outputs before the dependency list, so a list is never found without
+            // its outputs.
+            storage.put(&self.bundle_key(&key, &dependencies), &bincode::serialize(&bundle)?)?;
+            storage.put(&key, &bincode::serialize(&dependencies)?)
+        });
+
+        if let Err(err) = result {
+            warn!("Failed to put {} into shared cache. {}", name.display(), err);
+        }
+    }
+
+    /// Gets the fingerprint of everything other than the source files that affects the
+    /// outputs of asset.
+    fn fingerprint(&self, name: &Path, metadata: &AssetMetadata) -> Result<String> {
+        let version = Self::importer(&self.exts, &self.importers, name)
+            .map(|i| i.version())
+            .unwrap_or(0);
+
+        let params = serde_json::to_string(&metadata.params)?;
+        let mut contents = format!("{}|{:?}|{}", version, self.platform, params);
+        if let Some(v) = self.compression {
+            contents.push_str(&format!("|{:?}", v));
+        }
+
+        let mut hasher = XxHash::with_seed(0);
+        hasher.write(contents.as_bytes());
+        Ok(format!("{:016X}", hasher.finish()))
+    }
+
+    /// Runs `job` for each of assets concurrently. Every job owns the cache item of its asset
+    /// while running, and the captured logs are printed in the order of `names` after all the
+    /// jobs are done.
+    fn run<F, R>(&mut self, names: Vec<PathBuf>, job: F) -> Vec<(PathBuf, Result<R>)>
+    where
+        F: Fn(&AssetDatabase, &Path, &mut AssetCacheItem) -> Result<R> + Sync,
+        R: Send,
+    {
+        let mut items: Vec<_> = names
+            .into_iter()
+            .map(|v| {
+                let item = self.cache.take(&v);
+                (v, item)
+            }).collect();
+
+        let results: Vec<_> = {
+            let db = &*self;
+            db.pool.install(|| {
+                items
+                    .par_iter_mut()
+                    .map(|&mut (ref name, ref mut item)| {
+                        logs::capture(|| {
+                            let time = Instant::now();
+                            let result = job(db, name, item);
+                            (result, time.elapsed())
+                        })
+                    })
+                    .collect()
+            })
+        };
+
+        items
+            .into_iter()
+            .zip(results)
+            .map(|((name, item), ((result, duration), captured))| {
+                logs::flush(&captured);
+                self.cache.put(&name, item);
+
+                let report = self.report_of(&name);
+                report.duration += duration.as_secs() as f64 + f64::from(duration.subsec_nanos()) * 1e-9;
+
+                for v in captured {
+                    if v.warning {
+                        report.warnings.push(v.message);
+                    }
+                }
+
+                if let Err(ref err) = result {
+                    report.status = AssetStatus::Failed;
+                    report.errors.push(format!("{}", err));
+                }
+
+                (name, result)
+            }).collect()
+    }
+
+    /// Gets the report entry of asset in current build.
+    fn report_of(&mut self, name: &Path) -> &mut AssetReport {
+        self.report
+            .entry(name.to_owned())
+            .or_insert_with(|| AssetReport::new(name))
+    }
+
+    /// Marks the asset as failed, so it will be rebuilt in next build.
+    fn fail(&mut self, name: &Path) {
+        self.cache.item(name).fingerprint.clear();
+        self.report_of(name).status = AssetStatus::Failed;
+    }
+
+    /// Checks if the asset has failed in current build.
+    fn failed(&self, name: &Path) -> bool {
+        self.report
+            .get(name)
+            .map(|v| v.status == AssetStatus::Failed)
+            .unwrap_or(false)
+    }
+
+    /// Prints the summary of failed assets, and returns error if there are any.
+    fn failures(&self) -> Result<()> {
+        let failed: Vec<_> = self
+            .report
+            .values()
+            .filter(|v| v.status == AssetStatus::Failed)
+            .collect();
+
+        if failed.is_empty() {
+            return Ok(());
+        }
+
+        for v in &failed {
+            warn!("Failed to build {}. {}", v.name.display(), v.errors.join(" "));
+        }
+
+        bail!("{} of {} assets failed to build.", failed.len(), self.report.len());
+    }
+
+    /// Removes the asset and its resources, which happens if the asset file has been deleted.
+    fn unload(&mut self, name: &Path) -> Result<()> {
+        if let Some(metadata) = self.assets.remove(name) {
+            for r in &metadata.resources {
                 let dst = self.resources_dir.join(format!("{:X}", r.uuid.to_simple()));
+                if dst.exists() {
+                    fs::remove_file(dst)?;
+                }
+            }
+        }
+
+        self.trash(name)
+    }
+
+    /// Re-associates the meta-files of `missing` assets with the new `files` that have the
+    /// same contents, which happens if assets are moved or renamed without their meta-files.
+    fn recover(&mut self, missing: &[PathBuf], files: &[PathBuf]) -> Result<()> {
+        // Finds the orphaned meta-files, and the contents of their assets in last build.
+        let mut orphans: HashMap<String, Vec<PathBuf>> = HashMap::new();
+        for v in missing {
+            if !self.mounts.join(AssetMetadata::with_extension(v)).exists() {
+                continue;
+            }
+
+            if let Some(item) = self.cache.get(v) {
+                if !item.file.hash.is_empty() {
+                    orphans.entry(item.file.hash.clone()).or_default().push(v.clone());
+                }
+            }
+        }
+
+        if orphans.is_empty() {
+            return Ok(());
+        }
+
+        for v in files {
+            let metafile = self.mounts.join(AssetMetadata::with_extension(v));
+            if metafile.exists() {
+                continue;
+            }
+
+            let from = match orphans.get_mut(&cache::hash(&self.mounts.join(v))?) {
+                Some(ref mut names) if !names.is_empty() => names.remove(0),
+                _ => continue,
+            };
 
-                if src.exists() {
-                    let location = if k == &r.name {
-                        k.clone()
-                    } else {
-                        k.join(&r.name)
-                    };
-
-                    fs::copy(&src, &dst)?;
-                    manifest.items.push(ManifestItem {
-                        filename: manifest.buf.extend_from_str(location.to_str().unwrap()),
-                        dependencies: manifest.buf.extend_from_slice(&[]),
-                        uuid: r.uuid,
-                    });
+            fs::rename(self.mounts.join(AssetMetadata::with_extension(&from)), &metafile)?;
+            // Updates the name in meta-file, the broken ones are reported while loading.
+            let source = fs::read_to_string(&metafile)?;
+            if let Ok(mut metadata) = toml::de::from_str::<AssetMetadata>(&source) {
+                metadata.rename(v);
+                metadata.save(&self.mounts, v)?;
+            }
+
+            self.assets.remove(&from);
+            self.cache.rename(&from, v);
+            self.cache.touch(v)?;
+
+            warn!(
+                "Re-associates the meta-file of {:?} with {:?}, since they have the same contents.",
+                from, v
+            );
+        }
+
+        Ok(())
+    }
+
+    /// Moves the meta-file of missing asset into trash folder instead of deleting it, so the
+    /// UUIDs in it could be recovered by hand.
+    fn trash(&self, name: &Path) -> Result<()> {
+        let n = AssetMetadata::with_extension(name);
+        let metafile = self.mounts.join(&n);
+        if !metafile.exists() {
+            return Ok(());
+        }
+
+        // Keeps the ones that trashed before with the same name.
+        let mut dst = self.trash_dir.join(&n);
+        let mut i = 1;
+        while dst.exists() {
+            dst = self.trash_dir.join(format!("{}.{}", n.display(), i));
+            i += 1;
+        }
+
+        fs::create_dir_all(dst.parent().unwrap())?;
+        if fs::rename(&metafile, &dst).is_err() {
+            fs::copy(&metafile, &dst)?;
+            fs::remove_file(&metafile)?;
+        }
+
+        warn!("A meta data file ({:?}) exists but its asset can't be found, it has been moved \
+            to {:?}. When moving or deleting files, please ensure that the corresponding .meta.toml \
+            file is moved or deleted along with it.", n, dst);
+
+        Ok(())
+    }
+
+    /// Regenerates the UUIDs of assets that collide with the ones of other assets, which
+    /// happens if an asset is copied together with its meta-file. The oldest asset keeps its
+    /// UUIDs, and the names of assets that get new UUIDs are returned.
+    fn dedup(&mut self, names: &[PathBuf]) -> Result<Vec<PathBuf>> {
+        // Reads the meta-files of `names` again since they might be changed, and the broken
+        // ones are reported while loading.
+        let mut metadatas = self.assets.clone();
+        for v in names {
+            let metafile = self.mounts.join(AssetMetadata::with_extension(v));
+            if let Ok(source) = fs::read_to_string(&metafile) {
+                if let Ok(mut metadata) = toml::de::from_str::<AssetMetadata>(&source) {
+                    metadata.rename(v);
+                    metadatas.insert(v.clone(), metadata);
+                }
+            }
+        }
+
+        let mut owners: BTreeMap<Uuid, BTreeSet<PathBuf>> = BTreeMap::new();
+        for (k, v) in &metadatas {
+            owners.entry(v.uuid).or_default().insert(k.clone());
+            for r in &v.resources {
+                owners.entry(r.uuid).or_default().insert(k.clone());
+            }
+        }
+
+        let mut duplicates = BTreeMap::new();
+        for (_, v) in owners {
+            if v.len() > 1 {
+                let mut v: Vec<_> = v.into_iter().map(|n| (self.added(&n), n)).collect();
+                v.sort();
+
+                for (_, n) in v.iter().skip(1) {
+                    duplicates.insert(n.clone(), v[0].1.clone());
+                }
+            }
+        }
+
+        for (name, origin) in &duplicates {
+            let metadata = metadatas.get_mut(name).unwrap();
+            let mut changes = Vec::new();
+
+            let uuid = if self.reproducible {
+                AssetMetadata::derive_uuid(name)
+            } else {
+                Uuid::new_v4()
+            };
+
+            changes.push(format!("{} -> {}", metadata.uuid, uuid));
+            metadata.uuid = uuid;
+
+            let asset = metadata.uuid;
+            for r in &mut metadata.resources {
+                let uuid = if self.reproducible {
+                    ResourceMetadata::derive(&asset, r.name.clone(), r.tp).uuid
                 } else {
-                    println!("NOT EXITS {}", r.name.display());
+                    Uuid::new_v4()
+                };
+                changes.push(format!("{:?} {} -> {}", r.name, r.uuid, uuid));
+                r.uuid = uuid;
+            }
+
+            metadata.save(&self.mounts, name)?;
+            self.assets.remove(name);
+            self.cache.item(name).metafile(true);
+
+            let message = format!(
+                "The UUIDs of {:?} collide with {:?}, which might be copied with its meta-file. \
+                 Regenerates the UUIDs of the newer one: {}.",
+                name,
+                origin,
+                changes.join(", ")
+            );
+
+            warn!("{}", message);
+            self.report_of(name).warnings.push(message);
+        }
+
+        Ok(duplicates.into_iter().map(|v| v.0).collect())
+    }
+
+    /// Gets the time in seconds when the meta-file of asset is added, which is the time of
+    /// commit that adds it if it's tracked by git, or the modified time otherwise. The untracked
+    /// ones are always newer than the tracked ones.
+    fn added(&self, name: &Path) -> (bool, u64) {
+        let metafile = self.mounts.join(AssetMetadata::with_extension(name));
+        let dir = metafile.parent().unwrap();
+
+        let output = Command::new("git")
+            .args(&["log", "-1", "--diff-filter=A", "--format=%ct", "--"])
+            .arg(&metafile)
+            .current_dir(dir)
+            .output();
+
+        if let Ok(v) = output {
+            if v.status.success() {
+                if let Ok(v) = String::from_utf8_lossy(&v.stdout).trim().parse() {
+                    return (false, v);
                 }
             }
         }
 
-        // Generates a manifest to locate resources at runtime.
-        let mut o = fs::File::create(&self.resources_dir.join(manifest::NAME))?;
-        o.write_all(&manifest::MAGIC)?;
-        bincode::serialize_into(&mut o, &manifest)?;
+        let mtime = fs::metadata(&metafile)
+            .and_then(|v| v.modified())
+            .ok()
+            .and_then(|v| v.duration_since(UNIX_EPOCH).ok())
+            .map(|v| v.as_secs())
+            .unwrap_or(0);
 
-        self.cache.save()?;
+        (true, mtime)
+    }
+
+    /// Generates a manifest to locate resources at runtime.
+    fn save_manifest(&self) -> Result<()> {
+        let mut manifest = Manifest::new();
+        for (k, v) in &self.assets {
+            for r in &v.resources {
+                let dst = self.resources_dir.join(format!("{:X}", r.uuid.to_simple()));
+                if !dst.exists() {
+                    continue;
+                }
+
+                let location = if k == &r.name {
+                    k.clone()
+                } else {
+                    k.join(&r.name)
+                };
+
+                manifest.items.push(ManifestItem {
+                    filename: manifest.buf.extend_from_str(location.to_str().unwrap()),
+                    dependencies: manifest.buf.extend_from_slice(&[]),
+                    uuid: r.uuid,
+                });
+            }
+        }
+
+        let mut bytes = Vec::new();
+        bytes.write_all(&manifest::MAGIC)?;
+        bincode::serialize_into(&mut bytes, &manifest)?;
+        utils::write_atomically(&self.resources_dir.join(manifest::NAME), &bytes)?;
         Ok(())
     }
 
+    /// Removes the resource files that are not produced by any asset, and the temporary files
+    /// left by interrupted builds. Files that are not named after UUID are left untouched.
+    fn strip_resources(&self) -> Result<()> {
+        let mut resources = HashSet::new();
+        for v in self.assets.values() {
+            for r in &v.resources {
+                resources.insert(format!("{:X}", r.uuid.to_simple()));
+            }
+        }
+
+        for e in fs::read_dir(&self.resources_dir)? {
+            let path = e?.path();
+            if !path.is_file() {
+                continue;
+            }
+
+            let name = match path.file_name().and_then(|v| v.to_str()) {
+                Some(v) => v.to_owned(),
+                None => continue,
+            };
+
+            let orphan = if name.ends_with(utils::TMP_EXTENSION) {
+                true
+            } else {
+                Uuid::parse_str(&name).is_ok() && !resources.contains(&name)
+            };
+
+            if orphan {
+                fs::remove_file(&path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Checks if the resource at `dst` is missing or differs from its intermediate `src`.
+    fn outdated(src: &Path, dst: &Path) -> Result<bool> {
+        let dst = match fs::metadata(dst) {
+            Ok(v) => v,
+            Err(_) => return Ok(true),
+        };
+
+        let src = fs::metadata(src)?;
+        Ok(src.len() != dst.len() || src.modified()? > dst.modified()?)
+    }
+
     fn scan(&mut self) -> Result<()> {
         let mut metafiles: HashSet<PathBuf> = HashSet::new();
         let mut files = HashSet::new();
 
-        for e in WalkDir::new(&self.assets_dir).into_iter().filter_map(|e| {
-            if let Ok(e) = e {
-                if e.path()
-                    .file_name()
-                    .and_then(|n| n.to_str())
-                    .map(|s| !s.starts_with("."))
-                    .unwrap_or(false)
+        for &(_, ref dir) in self.mounts.dirs() {
+            for e in WalkDir::new(dir).into_iter().filter_map(|e| {
+                if let Ok(e) = e {
+                    if e.path()
+                        .file_name()
+                        .and_then(|n| n.to_str())
+                        .map(|s| !s.starts_with("."))
+                        .unwrap_or(false)
+                    {
+                        return Some(e);
+                    }
+                }
+
+                None
+            }) {
+                if e.file_type().is_dir() {
+                    continue;
+                }
+
+                let relative = self.mounts.name(e.path()).unwrap();
+
+                // The files in main assets folder are shadowed by the folder that mounted at
+                // the same prefix.
+                if self.mounts.join(&relative) != e.path() {
+                    warn!("{:?} is shadowed by mounted folder, and will be ignored.", e.path());
+                    continue;
+                }
+
                 {
-                    return Some(e);
+                    let relative_str = relative.to_str().unwrap();
+                    if relative_str.ends_with(EXTENSION) {
+                        let file = relative_str.trim_right_matches(EXTENSION);
+                        metafiles.insert(file.into());
+                        continue;
+                    }
+                }
+
+                if !self.mounts.excluded(&relative) {
+                    files.insert(relative);
                 }
             }
+        }
 
-            None
-        }) {
-            if e.file_type().is_dir() {
-                continue;
+        // Imports meta-files into database.
+        self.assets.clear();
+        let mut files: Vec<_> = files.into_iter().collect();
+        files.sort();
+
+        for v in &files {
+            metafiles.remove(v);
+        }
+
+        // Leaves the meta-files of excluded assets in place, so their UUIDs are kept if they
+        // are included again. The others are re-associated with new files or moved into trash.
+        let mut orphans: Vec<_> = metafiles
+            .into_iter()
+            .filter(|v| !(self.mounts.excluded(v) && self.mounts.join(v).is_file()))
+            .collect();
+        orphans.sort();
+
+        self.recover(&orphans, &files)?;
+        for v in &orphans {
+            self.trash(v)?;
+        }
+
+        self.dedup(&files)?;
+
+        self.load_assets(files)?;
+
+        self.cache.strip(&self.assets)?;
+        Ok(())
+    }
+
+    /// Loads the assets into database concurrently.
+    fn load_assets(&mut self, names: Vec<PathBuf>) -> Result<()> {
+        let mut rebuilt = HashSet::new();
+
+        for level in self.cache.levels(names) {
+            // Invalidates the assets whose dependencies have been rebuilt in previous levels.
+            for v in &level {
+                if self
+                    .cache
+                    .item(v)
+                    .dependencies
+                    .keys()
+                    .any(|d| rebuilt.contains(d))
+                {
+                    self.cache.item(v).file(true);
+                    self.report_of(v).reason = Some("dependencies rebuilt".into());
+                }
             }
 
-            let relative = e.path().strip_prefix(&self.assets_dir).unwrap().to_owned();
+            for (name, result) in self.run(level, |db, name, item| db.load(name, item)) {
+                let (modified, restored, input_size) = {
+                    let item = self.cache.item(&name);
+                    let mut input_size = fs::metadata(self.mounts.join(&name)).map(|v| v.len()).unwrap_or(0);
+                    for v in item.dependencies.keys() {
+                        input_size += fs::metadata(self.mounts.join(v)).map(|v| v.len()).unwrap_or(0);
+                    }
 
-            {
-                let relative_str = relative.to_str().unwrap();
-                if relative_str.ends_with(EXTENSION) {
-                    let file = relative_str.trim_right_matches(EXTENSION);
-                    metafiles.insert(file.into());
-                    continue;
+                    (item.modified(), item.restored, input_size)
+                };
+
+                if modified {
+                    rebuilt.insert(name.clone());
+                }
+
+                let report = self.report_of(&name);
+                report.input_size = input_size;
+
+                match result {
+                    Ok((metadata, reason)) => {
+                        if report.reason.is_none() {
+                            report.reason = reason.map(|v| v.to_owned());
+                        }
+
+                        report.status = if restored {
+                            AssetStatus::Restored
+                        } else if modified || report.reason.is_some() {
+                            AssetStatus::Rebuilt
+                        } else {
+                            AssetStatus::Skipped
+                        };
+
+                        self.assets.insert(name, metadata);
+                    }
+                    Err(v) => {
+                        // Stops at the first failure, so the assets that depend on it are
+                        // never compiled. The corrupted files are always skipped instead.
+                        if !self.keep_going && v.downcast_ref::<CorruptedAsset>().is_none() {
+                            bail!("Failed to compile {}. {}", name.display(), v);
+                        }
+
+                        self.fail(&name);
+
+                        // Keeps the previous resources of failed asset if its meta-file is still
+                        // readable, otherwise it will be excluded from the manifest.
+                        if let Ok(metadata) = AssetMetadata::import_from(&self.mounts, &name) {
+                            self.assets.insert(name, metadata);
+                        }
+                    }
                 }
             }
+        }
+
+        Ok(())
+    }
 
-            files.insert(relative);
+    /// Imports the meta-file of asset, and generates its essential intermediate files. Returns
+    /// the metadata and the reason of rebuilding.
+    fn load(&self, name: &Path, item: &mut AssetCacheItem) -> Result<(AssetMetadata, Option<&'static str>)> {
+        // Derives the UUID of new asset from its name in reproducible builds.
+        let created = !self.mounts.join(AssetMetadata::with_extension(name)).exists();
+        let mut metadata = AssetMetadata::import_from(&self.mounts, name)?;
+        if created && self.reproducible {
+            metadata.uuid = AssetMetadata::derive_uuid(name);
+            metadata.save(&self.mounts, name)?;
         }
 
-        // Imports meta-files into database.
-        self.assets.clear();
-        for v in files {
-            metafiles.remove(&v);
+        let fingerprint = self.fingerprint(name, &metadata)?;
+        item.formats.clear();
+
+        let reason = if item.fingerprint.is_empty() {
+            Some("not built before")
+        } else if item.file.modified {
+            Some("asset file modified")
+        } else if item.metafile.modified {
+            Some("meta-file modified")
+        } else if item.dependencies.values().any(|v| v.modified) {
+            Some("dependencies modified")
+        } else if item.fingerprint != fingerprint {
+            Some("importer, platform or params changed")
+        } else {
+            None
+        };
 
-            let mut metadata = AssetMetadata::import_from(&self.assets_dir, &v)?;
+        // Recompiles the asset if importer, platform or params have been changed since the
+        // last import.
+        if item.fingerprint != fingerprint {
+            item.file(true);
+        }
+
+        // Restores the outputs from shared cache if someone has built exactly the same asset
+        // before, otherwise generates essential intermediate files.
+        item.restored = item.modified() && self.restore(name, &metadata, item);
 
-            // Generates essential intermediate files.
-            if let Some(i) = Self::importer(&self.exts, &self.importers, &v) {
+        if !item.restored {
+            // Dependencies are registered again while compiling.
+            if item.modified() {
+                item.dependencies.clear();
+            }
+
+            if let Some(i) = Self::importer(&self.exts, &self.importers, name) {
                 let mut db = AssetIntermediateGenerator {
-                    assets_dir: &self.assets_dir,
-                    cache: &mut self.cache,
+                    mounts: &self.mounts,
+                    dir: self.cache.dir(),
+                    cache: item,
                     metadata: &metadata,
                     platform: self.platform,
+                    compression: self.compression,
                 };
 
                 i.compile(&mut db)?;
             }
-
-            // Updates meta-file.
-            let modified = {
-                let mut db = AssetMetadataGenerator::new(&mut self.cache, &mut metadata);
-                if let Some(i) = Self::importer(&self.exts, &self.importers, &v) {
-                    i.compile_metadata(&mut db)?;
-                }
-                db.modified()
-            };
-
-            if modified {
-                self.cache.metafile(&v, true);
-                metadata.save(&self.assets_dir, &v)?;
-            }
-
-            self.assets.insert(v, metadata);
         }
 
-        // Removes all the un-associated meta-files.
-        for v in metafiles.drain() {
-            let n = AssetMetadata::with_extension(&v);
-            let metafile = self.assets_dir.join(&n);
-            fs::remove_file(metafile)?;
+        // Updates meta-file.
+        let modified = {
+            let mut db =
+                AssetMetadataGenerator::new(self.cache.dir(), &self.defaults, self.reproducible, item, &mut metadata);
+            if let Some(i) = Self::importer(&self.exts, &self.importers, name) {
+                i.compile_metadata(&mut db)?;
+            }
+            db.modified()
+        };
 
-            warn!("A meta data file ({:?}) exists but its asset can't be found. When moving \
-                or deleting files, please ensure that the corresponding .meta.toml file is moved or \
-                deleted along with it.", n);
+        if modified {
+            item.metafile(true);
+            metadata.save(&self.mounts, name)?;
         }
 
-        self.cache.strip(&self.assets)?;
-        Ok(())
+        Ok((metadata, reason))
     }
 
     fn importer<'a, T: AsRef<Path>>(
@@ -247,14 +1130,23 @@ impl AssetDatabase {
 }
 
 pub struct AssetMetadataGenerator<'a> {
-    cache: &'a mut AssetCache,
+    dir: &'a Path,
+    defaults: &'a [(Pattern, DefaultParams)],
+    reproducible: bool,
+    cache: &'a mut AssetCacheItem,
     metadata: &'a mut AssetMetadata,
     table: HashMap<PathBuf, ResourceType>,
     modified: bool,
 }
 
 impl<'a> AssetMetadataGenerator<'a> {
-    pub fn new(cache: &'a mut AssetCache, metadata: &'a mut AssetMetadata) -> Self {
+    pub fn new(
+        dir: &'a Path,
+        defaults: &'a [(Pattern, DefaultParams)],
+        reproducible: bool,
+        cache: &'a mut AssetCacheItem,
+        metadata: &'a mut AssetMetadata,
+    ) -> Self {
         let mut table = HashMap::new();
         let mut modified = false;
 
@@ -269,6 +1161,9 @@ impl<'a> AssetMetadataGenerator<'a> {
         }
 
         AssetMetadataGenerator {
+            dir: dir,
+            defaults: defaults,
+            reproducible: reproducible,
             cache: cache,
             metadata: metadata,
             table: table,
@@ -283,13 +1178,29 @@ impl<'a> AssetMetadataGenerator<'a> {
 
     /// Gets the parameters of resource entry.
     pub fn params(&self) -> AssetParams {
-        self.metadata.params
+        self.metadata.params.clone()
     }
 
-    /// Adds a new resource entry with parameters.
+    /// Updates the parameters of asset. The fields of the first default rule in workspace.toml
+    /// that matches this asset override the ones in `params`.
     pub fn update_params(&mut self, params: AssetParams) {
         self.modified = true;
-        self.metadata.params = params;
+        self.metadata.params = self.seed(params);
+    }
+
+    fn seed(&self, params: AssetParams) -> AssetParams {
+        // The rules without `type` are skipped for the importers that they don't fit.
+        for &(ref pattern, ref rule) in self.defaults {
+            if !pattern.matches(&self.metadata.name) {
+                continue;
+            }
+
+            if let Some(Ok(v)) = rule.apply(&params) {
+                return v;
+            }
+        }
+
+        params
     }
 
     pub fn add<T: AsRef<Path>>(&mut self, name: T, tp: ResourceType) {
@@ -301,7 +1212,12 @@ impl<'a> AssetMetadataGenerator<'a> {
             }
         }
 
-        let rmd = ResourceMetadata::new(name.to_owned(), tp);
+        let rmd = if self.reproducible {
+            ResourceMetadata::derive(&self.metadata.uuid, name.to_owned(), tp)
+        } else {
+            ResourceMetadata::new(name.to_owned(), tp)
+        };
+
         self.metadata.resources.push(rmd);
         self.table.insert(name.to_owned(), tp);
         self.modified = true;
@@ -310,8 +1226,8 @@ impl<'a> AssetMetadataGenerator<'a> {
     /// Gets the full path to specified intermediate file.
     pub fn intermediate<T: AsRef<Path>>(&mut self, name: T, modified: bool) -> PathBuf {
         let name = name.as_ref();
-        let md = self.cache.intermediate(&self.metadata.name, name, modified);
-        let mut path = self.cache.dir().join(format!("{:X}", md.uuid.to_simple()));
+        let md = self.cache.intermediate(name, modified);
+        let mut path = self.dir.join(format!("{:X}", md.uuid.to_simple()));
         if let Some(extension) = name.extension() {
             path = path.with_extension(extension);
         }
@@ -325,10 +1241,12 @@ impl<'a> AssetMetadataGenerator<'a> {
 }
 
 pub struct AssetIntermediateGenerator<'a> {
-    assets_dir: &'a Path,
-    cache: &'a mut AssetCache,
+    mounts: &'a Mounts,
+    dir: &'a Path,
+    cache: &'a mut AssetCacheItem,
     metadata: &'a AssetMetadata,
     platform: RuntimePlatform,
+    compression: Option<Compression>,
 }
 
 impl<'a> AssetIntermediateGenerator<'a> {
@@ -340,9 +1258,19 @@ impl<'a> AssetIntermediateGenerator<'a> {
         self.platform
     }
 
-    /// Gets the importing params for resource.
+    /// Gets the importing params for resource, with the compression overridden by build
+    /// profile.
     pub fn params(&self) -> AssetParams {
-        self.metadata.params
+        let mut params = self.metadata.params.clone();
+        if let Some(v) = self.compression {
+            match params {
+                AssetParams::Texture(ref mut p) => p.compression = v,
+                AssetParams::Audio(ref mut p) => p.compression = v,
+                _ => {}
+            }
+        }
+
+        params
     }
 
     // Gets the universal-uniqued identifier for resource.
@@ -359,28 +1287,59 @@ impl<'a> AssetIntermediateGenerator<'a> {
 
     /// Gets the full path to asset file.
     pub fn path(&self) -> PathBuf {
-        self.assets_dir.join(&self.metadata.name)
+        self.mounts.join(&self.metadata.name)
     }
 
-    /// Checks if the asset or its metadat has been modified.
+    /// Records the format that chosen for the outputs of this asset, which is written into
+    /// build report.
+    pub fn record_format<T: Into<String>>(&mut self, format: T) {
+        self.cache.formats.push(format.into());
+    }
+
+    /// Checks if the asset, its metadata or any of its dependencies has been modified.
     pub fn modified(&mut self) -> bool {
-        self.cache.file(&self.metadata.name, false).modified
-            || self.cache.metafile(&self.metadata.name, false).modified
+        self.cache.modified()
+    }
+
+    /// Registers another source file that is read while compiling this asset, so changing it
+    /// triggers a rebuild. The `uri` is relative to the folder of this asset. Returns the full
+    /// path to the file, or `None` if it is outside of assets folder.
+    pub fn dependency<T: AsRef<Path>>(&mut self, uri: T) -> Option<PathBuf> {
+        let name = self
+            .metadata
+            .name
+            .parent()
+            .map(|v| v.join(uri.as_ref()))
+            .and_then(utils::normalize);
+
+        match name {
+            Some(name) => {
+                self.cache.dependency(&name);
+                Some(self.mounts.join(name))
+            }
+            None => {
+                warn!(
+                    "{} references {} which is outside of assets folder.",
+                    self.metadata.name.display(),
+                    uri.as_ref().display()
+                );
+
+                None
+            }
+        }
     }
 
     /// Checks if the intermediate file has been modified.
     pub fn intermediate_modified<T: AsRef<Path>>(&mut self, name: T) -> bool {
         let name = name.as_ref();
-        self.cache
-            .intermediate(&self.metadata.name, name, false)
-            .modified
+        self.cache.intermediate(name, false).modified
     }
 
     /// Gets the full path to specified intermediate file.
     pub fn intermediate<T: AsRef<Path>>(&mut self, name: T, modified: bool) -> PathBuf {
         let name = name.as_ref();
-        let md = self.cache.intermediate(&self.metadata.name, name, modified);
-        let mut path = self.cache.dir().join(format!("{:X}", md.uuid.to_simple()));
+        let md = self.cache.intermediate(name, modified);
+        let mut path = self.dir.join(format!("{:X}", md.uuid.to_simple()));
         if let Some(extension) = name.extension() {
             path = path.with_extension(extension);
         }
@@ -391,15 +1350,97 @@ impl<'a> AssetIntermediateGenerator<'a> {
     /// Checks if the resource file has been modified.
     pub fn resource_modified<T: AsRef<Path>>(&mut self, name: T) -> bool {
         let name = name.as_ref();
-        self.cache
-            .resource(&self.metadata.name, name, false)
-            .modified
+        self.cache.resource(name, false).modified
     }
 
     /// Gets the full path to specified resource file.
     pub fn resource<T: AsRef<Path>>(&mut self, name: T, modified: bool) -> PathBuf {
         let name = name.as_ref();
-        let md = self.cache.resource(&self.metadata.name, name, modified);
-        self.cache.dir().join(format!("{:X}", md.uuid.to_simple()))
+        let md = self.cache.resource(name, modified);
+        self.dir.join(format!("{:X}", md.uuid.to_simple()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::super::cache::CheckMode;
+    use super::super::{storage, Layout};
+    use super::*;
+
+    /// Creates a fresh workspace that shares the cache at `shared`.
+    fn fixture(name: &str, shared: &Path) -> PathBuf {
+        let root = ::std::env::temp_dir().join(format!("crayon-cli-{}-{}", process::id(), name));
+        if root.exists() {
+            fs::remove_dir_all(&root).unwrap();
+        }
+
+        fs::create_dir_all(root.join("assets")).unwrap();
+        fs::write(
+            root.join("workspace.toml"),
+            format!(
+                "version = 2\n\n[assets]\nsource = 'assets'\ndestination = 'resources'\nimporters = []\n\n[cache]\nshared = '{}'\n",
+                shared.display()
+            ),
+        ).unwrap();
+
+        fs::write(root.join("assets/a.txt"), "a").unwrap();
+        fs::write(root.join("assets/b.bin"), "b").unwrap();
+        root
+    }
+
+    fn open(root: &Path) -> AssetDatabase {
+        let options = Options {
+            platform: RuntimePlatform::Macos,
+            check: CheckMode::Combined,
+            jobs: 1,
+            cache_dir: None,
+            keep_going: false,
+            compression: None,
+            reproducible: true,
+            profile: None,
+        };
+
+        let layout = Layout::open(root, &options).unwrap();
+        let shared = layout.settings.cache.shared.clone().unwrap();
+        let storage = storage::open(&layout.root, &shared).unwrap();
+
+        AssetDatabase::new(
+            &layout.root,
+            layout.settings.assets,
+            &layout.cache_dir,
+            Some(storage),
+            &options,
+        ).unwrap()
+    }
+
+    #[test]
+    fn restore() {
+        let shared = ::std::env::temp_dir().join(format!("crayon-cli-{}-shared", process::id()));
+        let a = fixture("restore-a", &shared);
+        let b = fixture("restore-b", &shared);
+        let name = Path::new("a.txt");
+
+        // Pretends that a.txt reads b.bin while compiling, like the buffers of a glTF file.
+        let mut database = open(&a);
+        database.import_all().unwrap();
+        let metadata = database.assets[name].clone();
+        let mut item = database.cache.get(name).unwrap().clone();
+        item.dependency("b.bin");
+        database.store(name, &metadata, &item);
+
+        // Restores into an empty cache, where the dependencies are not known yet.
+        let database = open(&b);
+        let mut item = AssetCacheItem::new();
+        assert!(database.restore(name, &metadata, &mut item));
+        assert!(item.dependencies.contains_key(Path::new("b.bin")));
+
+        fs::write(b.join("assets/b.bin"), "c").unwrap();
+        assert!(!database.restore(name, &metadata, &mut AssetCacheItem::new()));
+
+        for v in &[a, b, shared] {
+            fs::remove_dir_all(v).unwrap();
+        }
     }
 }
@@ -0,0 +1,562 @@
+//! Sub-commands that manage the resources and intermediates of workspace without building.
+
+use std::collections::HashSet;
+use std::env;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use toml;
+use walkdir::WalkDir;
+
+use super::cache::{self, AssetCache};
+use super::database::TRASH;
+use super::metadata::{AssetMetadata, EXTENSION};
+use super::settings::{self, Settings};
+use super::utils;
+use super::{Layout, Options, Result};
+use platform::RuntimePlatform;
+
+/// Creates a workspace at `root` with default settings, the assets and resources folders,
+/// and a `.gitignore` that excludes intermediates and trash. The existing workspace.toml is only
+/// overwritten if `force` is set.
+pub fn init<T: AsRef<Path>>(root: T, force: bool) -> Result<()> {
+    let root = root.as_ref();
+    let file = root.join(settings::NAME);
+    if file.exists() && !force {
+        bail!(
+            "The workspace.toml already exists at {:?}, use --force to overwrite it.",
+            root
+        );
+    }
+
+    let params: Settings = toml::de::from_str(settings::TEMPLATE)?;
+
+    fs::create_dir_all(root)?;
+    fs::write(&file, settings::TEMPLATE)?;
+    info!("Creates {:?}.", file);
+
+    for v in &[&params.assets.source, &params.assets.destination] {
+        let dir = root.join(v);
+        if !dir.exists() {
+            fs::create_dir_all(&dir)?;
+            info!("Creates {:?}.", dir);
+        }
+    }
+
+    // Appends the intermediates and trash folders to .gitignore, and leaves the other entries
+    // untouched.
+    let ignore = root.join(".gitignore");
+    let mut contents = fs::read_to_string(&ignore).unwrap_or_default();
+    for entry in &[format!("/{}/", params.cache.dir.display()), format!("/{}/", TRASH)] {
+        if !contents.lines().any(|v| v.trim() == entry) {
+            if !contents.is_empty() && !contents.ends_with('\n') {
+                contents.push('\n');
+            }
+
+            contents.push_str(entry);
+            contents.push('\n');
+            fs::write(&ignore, &contents)?;
+            info!("Adds {} to {:?}.", entry, ignore);
+        }
+    }
+
+    Ok(())
+}
+
+/// Validates workspace.toml and every meta-file in assets folder without building.
+pub fn check<T: AsRef<Path>>(root: T, options: &Options) -> Result<()> {
+    let layout = Layout::open(root, options)?;
+    let mut problems = 0;
+
+    let mut exts = HashSet::new();
+    for v in &layout.settings.assets.importers {
+        for e in &v.extensions {
+            if !exts.insert(e.trim_left_matches('.')) {
+                warn!("File extension {:?} has more than one importer.", e);
+                problems += 1;
+            }
+        }
+    }
+
+    let mut metafiles = 0;
+    for &(_, ref dir) in layout.mounts.dirs() {
+        if !dir.is_dir() {
+            bail!("The assets folder ({:?}) is not exists.", dir);
+        }
+
+        let walker = WalkDir::new(dir)
+            .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+            .into_iter()
+            .filter_entry(|e| e.depth() == 0 || !e.file_name().to_string_lossy().starts_with('.'));
+
+        for e in walker {
+            let e = e?;
+            let name = match layout.mounts.name(e.path()) {
+                Some(v) => v.to_string_lossy().into_owned(),
+                None => continue,
+            };
+
+            if !e.file_type().is_file() || !name.ends_with(EXTENSION) {
+                continue;
+            }
+
+            metafiles += 1;
+
+            let source = fs::read_to_string(e.path())?;
+            match toml::de::from_str::<AssetMetadata>(&source) {
+                Ok(metadata) => {
+                    for key in unknown_params(&source, &metadata) {
+                        warn!("Meta-file {} has unknown key `params.{}`.", name, key);
+                        problems += 1;
+                    }
+                }
+                Err(err) => {
+                    warn!("Meta-file {} is broken. {}", name, utils::toml_error(&source, &err));
+                    problems += 1;
+                }
+            }
+
+            if !layout.mounts.join(name.trim_right_matches(EXTENSION)).is_file() {
+                warn!("Meta-file {} exists but its asset can't be found.", name);
+                problems += 1;
+            }
+        }
+    }
+
+    if problems > 0 {
+        bail!("Found {} problems in workspace.", problems);
+    }
+
+    info!("Checked workspace.toml and {} meta-files.", metafiles);
+    Ok(())
+}
+
+/// Gets the keys of params in meta-file that are dropped when it's parsed. The unknown keys of
+/// params structs are rejected while parsing, but `Bytes` has no struct to reject them.
+fn unknown_params(source: &str, metadata: &AssetMetadata) -> Vec<String> {
+    let value: toml::Value = match toml::de::from_str(source) {
+        Ok(v) => v,
+        Err(_) => return Vec::new(),
+    };
+
+    let parsed = match toml::Value::try_from(&metadata.params) {
+        Ok(v) => v,
+        Err(_) => return Vec::new(),
+    };
+
+    match (value.get("params").and_then(|v| v.as_table()), parsed.as_table()) {
+        (Some(params), Some(parsed)) => params
+            .keys()
+            .filter(|k| !parsed.contains_key(*k))
+            .cloned()
+            .collect(),
+        _ => Vec::new(),
+    }
+}
+
+/// Removes the resources and/or intermediates of workspace. Both are removed if neither is
+/// specified.
+pub fn clean<T: AsRef<Path>>(root: T, options: &Options, resources: bool, intermediates: bool) -> Result<()> {
+    let layout = Layout::open(root, options)?;
+    let (resources, intermediates) = if resources || intermediates {
+        (resources, intermediates)
+    } else {
+        (true, true)
+    };
+
+    if resources && layout.resources_dir.exists() {
+        info!("Removes resources at {:?}.", layout.resources_dir);
+        fs::remove_dir_all(&layout.resources_dir)?;
+    }
+
+    // Removes the intermediates that are built for every platform.
+    if intermediates {
+        for &v in &RuntimePlatform::all() {
+            let dir = cache::locate(&layout.cache_dir, &layout.root, &layout.mounts, v);
+            if dir.exists() {
+                info!("Removes intermediates at {:?}.", dir);
+                fs::remove_dir_all(&dir)?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Prints the statistics of intermediates.
+pub fn cache_stats<T: AsRef<Path>>(root: T, options: &Options) -> Result<()> {
+    let layout = Layout::open(root, options)?;
+    let options = resolve(&layout, options)?;
+    let cache = open(&layout, &options)?;
+    let stats = cache.stats()?;
+
+    println!("Intermediates: {}", cache.dir().display());
+    println!("    Assets: {}", stats.assets);
+    println!("    Files: {}", stats.files);
+    println!("    Size: {}", size(stats.bytes));
+
+    let mut others = 0;
+    for e in fs::read_dir(&layout.cache_dir)? {
+        let path = e?.path();
+        if cache::is_cache_dir(&path) && path != cache.dir() {
+            others += 1;
+        }
+    }
+
+    if others > 0 {
+        println!(
+            "There are caches of {} other workspaces or platforms in {}, which could be removed with `cache prune`.",
+            others,
+            layout.cache_dir.display()
+        );
+    }
+
+    Ok(())
+}
+
+/// Removes the caches of other workspaces and platforms, and the entries that are built for
+/// other platforms. The other folders in intermediates folder are left untouched.
+pub fn cache_prune<T: AsRef<Path>>(root: T, options: &Options) -> Result<()> {
+    let layout = Layout::open(root, options)?;
+    let options = resolve(&layout, options)?;
+    let mut cache = open(&layout, &options)?;
+
+    for e in fs::read_dir(&layout.cache_dir)? {
+        let path = e?.path();
+        if cache::is_cache_dir(&path) && path != cache.dir() {
+            info!("Removes cache of other workspace or platform at {:?}.", path);
+            fs::remove_dir_all(&path)?;
+        }
+    }
+
+    let len = cache.prune(options.platform)?;
+    cache.save()?;
+
+    info!("Removes {} entries that are built for other platforms.", len);
+    Ok(())
+}
+
+/// Verifies the checksums of intermediates, and drops the corrupted entries.
+pub fn cache_verify<T: AsRef<Path>>(root: T, options: &Options) -> Result<()> {
+    let layout = Layout::open(root, options)?;
+    let options = resolve(&layout, options)?;
+    let mut cache = open(&layout, &options)?;
+
+    let corrupted = cache.verify()?;
+    cache.save()?;
+
+    for v in &corrupted {
+        warn!("Intermediates of {} are missing or corrupted.", v.display());
+    }
+
+    info!(
+        "Verified intermediates, {} corrupted entries are dropped and will be rebuilt.",
+        corrupted.len()
+    );
+
+    Ok(())
+}
+
+/// Moves the asset or folder at `src` to `dst` together with meta-files, so the UUIDs of its
+/// resources are kept and the intermediates are reused. The paths are relative to current
+/// directory, and `dst` could be an existing folder to move into.
+pub fn mv<T1, T2, T3>(root: T1, options: &Options, src: T2, dst: T3) -> Result<()>
+where
+    T1: AsRef<Path>,
+    T2: AsRef<Path>,
+    T3: AsRef<Path>,
+{
+    let layout = Layout::open(root, options)?;
+    let cwd = env::current_dir()?;
+
+    let src = cwd.join(src);
+    if !src.exists() {
+        bail!("{:?} does not exist.", src);
+    }
+
+    let src = src.canonicalize()?;
+    let mut dst = utils::resolve(cwd.join(dst));
+    if dst.is_dir() {
+        dst = dst.join(src.file_name().unwrap());
+    }
+
+    if dst.exists() {
+        bail!("{:?} already exists.", dst);
+    }
+
+    // Never overwrites an orphaned meta-file, whose UUIDs might still be recovered.
+    let metafile = AssetMetadata::with_extension(&dst);
+    if metafile.exists() {
+        bail!("{:?} already exists.", metafile);
+    }
+
+    // Resolves the real path of destination through its nearest existing ancestor, so it could
+    // be mapped to an asset name before anything is created.
+    let dst = {
+        let mut base = dst.as_path();
+        let mut names = Vec::new();
+        while !base.exists() {
+            match (base.parent(), base.file_name()) {
+                (Some(parent), Some(name)) => {
+                    names.push(name);
+                    base = parent;
+                }
+                _ => bail!("{:?} is not a valid destination.", dst),
+            }
+        }
+
+        let mut real = base.canonicalize()?;
+        for v in names.iter().rev() {
+            real.push(v);
+        }
+
+        real
+    };
+
+    let (from, to) = match (layout.mounts.name(&src), layout.mounts.name(&dst)) {
+        (Some(from), Some(to)) => (from, to),
+        _ => bail!("Both {:?} and {:?} should be located in assets folders.", src, dst),
+    };
+
+    if from.as_os_str().is_empty() || to.starts_with(&from) {
+        bail!("Can not move {:?} into itself.", src);
+    }
+
+    if let Some(parent) = dst.parent() {
+        fs::create_dir_all(parent)?;
+    }
+
+    // Collects the assets to move.
+    let mut names = Vec::new();
+    if src.is_dir() {
+        for e in WalkDir::new(&src).into_iter().filter_map(|e| e.ok()) {
+            let name = layout.mounts.name(e.path()).unwrap();
+            if e.file_type().is_file() && !name.to_string_lossy().ends_with(EXTENSION) {
+                names.push(name);
+            }
+        }
+
+        fs::rename(&src, &dst)?;
+    } else {
+        names.push(from.clone());
+
+        fs::rename(&src, &dst)?;
+        let metafile = AssetMetadata::with_extension(&src);
+        if metafile.exists() {
+            fs::rename(&metafile, AssetMetadata::with_extension(&dst))?;
+        }
+    }
+
+    // Renames the entries in the caches of every platform, so nothing is rebuilt.
+    let mut caches = Vec::new();
+    for &v in &RuntimePlatform::all() {
+        let dir = cache::locate(&layout.cache_dir, &layout.root, &layout.mounts, v);
+        if cache::is_cache_dir(&dir) {
+            let mut options = options.clone();
+            options.platform = v;
+            caches.push(open(&layout, &options)?);
+        }
+    }
+
+    for v in names {
+        let name: PathBuf = if v == from {
+            to.clone()
+        } else {
+            to.join(v.strip_prefix(&from).unwrap())
+        };
+
+        // Updates the name in meta-file, and leaves the broken ones as they are so their
+        // UUIDs could be recovered by hand.
+        let metafile = layout.mounts.join(AssetMetadata::with_extension(&name));
+        if let Ok(source) = fs::read_to_string(&metafile) {
+            match toml::de::from_str::<AssetMetadata>(&source) {
+                Ok(mut metadata) => {
+                    metadata.rename(&name);
+                    metadata.save(&layout.mounts, &name)?;
+                }
+                Err(err) => warn!("Meta-file of {:?} is broken. {}", name, err),
+            }
+        }
+
+        for cache in &mut caches {
+            cache.rename(&v, &name);
+        }
+
+        info!("Moves {:?} to {:?}.", v, name);
+    }
+
+    for cache in &mut caches {
+        cache.save()?;
+    }
+
+    Ok(())
+}
+
+/// Applies the overrides of selected build profile to `options`.
+fn resolve(layout: &Layout, options: &Options) -> Result<Options> {
+    let mut options = options.clone();
+    if let Some(v) = options.profile.clone() {
+        options.apply(layout.settings.profile(&v)?);
+    }
+
+    Ok(options)
+}
+
+fn open(layout: &Layout, options: &Options) -> Result<AssetCache> {
+    AssetCache::open(
+        &layout.cache_dir,
+        &layout.root,
+        &layout.mounts,
+        options.platform,
+        options.check,
+    )
+}
+
+fn size(bytes: u64) -> String {
+    const UNITS: [&str; 4] = ["B", "KB", "MB", "GB"];
+
+    let mut v = bytes as f64;
+    let mut unit = 0;
+    while v >= 1024.0 && unit < UNITS.len() - 1 {
+        v /= 1024.0;
+        unit += 1;
+    }
+
+    format!("{:.1} {}", v, UNITS[unit])
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use super::super::cache::CheckMode;
+    use super::super::Workspace;
+    use super::*;
+
+    /// Creates a workspace that mounts `engine/assets` at `engine`, and builds it for two
+    /// platforms.
+    fn fixture(name: &str) -> (PathBuf, Options) {
+        let dir = env::temp_dir().join(format!("crayon-cli-{}-{}", process::id(), name));
+        if dir.exists() {
+            fs::remove_dir_all(&dir).unwrap();
+        }
+
+        fs::create_dir_all(dir.join("game/assets/dir/sub")).unwrap();
+        fs::create_dir_all(dir.join("engine/assets")).unwrap();
+        fs::write(
+            dir.join("game").join(settings::NAME),
+            "version = 2\n\n[assets]\nsource = 'assets'\ndestination = 'resources'\nimporters = []\n\n[[assets.mounts]]\nsource = '../engine/assets'\nprefix = 'engine'\n",
+        ).unwrap();
+
+        fs::write(dir.join("game/assets/a.txt"), "a").unwrap();
+        fs::write(dir.join("game/assets/dir/b.txt"), "b").unwrap();
+        fs::write(dir.join("game/assets/dir/sub/c.txt"), "c").unwrap();
+
+        let root = dir.join("game").canonicalize().unwrap();
+        let mut options = Options {
+            platform: RuntimePlatform::Macos,
+            check: CheckMode::Combined,
+            jobs: 1,
+            cache_dir: None,
+            keep_going: false,
+            compression: None,
+            reproducible: false,
+            profile: None,
+        };
+
+        for &v in &[RuntimePlatform::Macos, RuntimePlatform::Ios] {
+            options.platform = v;
+            Workspace::new(&root, options.clone()).unwrap().import_all().unwrap();
+        }
+
+        (root, options)
+    }
+
+    /// Checks that the asset has been moved with its meta-file in every cache.
+    fn moved(root: &Path, options: &Options, from: &str, to: &str) {
+        let layout = Layout::open(root, options).unwrap();
+        let metadata = AssetMetadata::import_from(&layout.mounts, to).unwrap();
+        assert_eq!(metadata.name, Path::new(to));
+        assert!(!layout.mounts.join(AssetMetadata::with_extension(from)).exists());
+
+        for &v in &[RuntimePlatform::Macos, RuntimePlatform::Ios] {
+            let mut options = options.clone();
+            options.platform = v;
+            let cache = open(&layout, &options).unwrap();
+            assert!(cache.get(from).is_none());
+            assert!(cache.get(to).is_some());
+        }
+    }
+
+    #[test]
+    fn folder() {
+        let (root, options) = fixture("mv-folder");
+        mv(&root, &options, root.join("assets/dir"), root.join("assets/moved")).unwrap();
+
+        assert!(root.join("assets/moved/sub/c.txt").exists());
+        moved(&root, &options, "dir/b.txt", "moved/b.txt");
+        moved(&root, &options, "dir/sub/c.txt", "moved/sub/c.txt");
+        fs::remove_dir_all(root.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn mounts() {
+        let (root, options) = fixture("mv-mounts");
+        let engine = root.join("../engine/assets");
+        mv(&root, &options, root.join("assets/a.txt"), engine.join("new/a.txt")).unwrap();
+
+        assert!(engine.join("new/a.txt").exists());
+        moved(&root, &options, "a.txt", "engine/new/a.txt");
+        fs::remove_dir_all(root.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn rejected() {
+        let (root, options) = fixture("mv-rejected");
+
+        // Nothing is created if the move is rejected.
+        let dst = root.join("assets/dir/sub/new/dir");
+        assert!(mv(&root, &options, root.join("assets/dir"), &dst).is_err());
+        assert!(!root.join("assets/dir/sub/new").exists());
+
+        let dst = root.join("../outside/a.txt");
+        assert!(mv(&root, &options, root.join("assets/a.txt"), &dst).is_err());
+        assert!(!root.join("../outside").exists());
+
+        fs::write(root.join("assets/d.txt.meta.toml"), "").unwrap();
+        assert!(mv(&root, &options, root.join("assets/a.txt"), root.join("assets/d.txt")).is_err());
+        assert!(root.join("assets/a.txt").exists());
+
+        fs::remove_dir_all(root.parent().unwrap()).unwrap();
+    }
+
+    #[test]
+    fn unknown() {
+        let (root, options) = fixture("check-unknown");
+        assert!(check(&root, &options).is_ok());
+
+        let metafile = root.join("assets/a.txt.meta.toml");
+        let source = fs::read_to_string(&metafile).unwrap();
+        fs::write(&metafile, source.replace("[params]", "[params]\nmipmap = false")).unwrap();
+
+        let metafile = root.join("assets/dir/b.txt.meta.toml");
+        let source = fs::read_to_string(&metafile).unwrap();
+        fs::write(&metafile, format!("tag = 'b'\n{}", source)).unwrap();
+
+        fs::write(root.join("assets/t.png"), "").unwrap();
+        fs::write(
+            root.join("assets/t.png.meta.toml"),
+            "name = 't.png'\nuuid = '6ab2a3d5-6a0e-4a7e-9a4b-8f2c1d3e4f50'\nresources = []\n\n[params]\ntype = 'Texture'\nmipmaps = false\n",
+        ).unwrap();
+
+        let err = check(&root, &options).unwrap_err();
+        assert_eq!(format!("{}", err), "Found 3 problems in workspace.");
+
+        // The broken meta-files are kept as they are while building.
+        let err = Workspace::new(&root, options.clone()).unwrap().import_all().unwrap_err();
+        assert!(format!("{}", err).contains("unknown field `tag`"), "{}", err);
+        assert!(fs::read_to_string(&metafile).unwrap().starts_with("tag = 'b'"));
+
+        fs::remove_dir_all(root.parent().unwrap()).unwrap();
+    }
+}
@@ -4,12 +4,14 @@ use std::path::{Path, PathBuf};
 use toml;
 use uuid::Uuid;
 
-use super::Result;
+use super::utils;
+use super::{Mounts, Result};
 use assets::{AssetParams, ResourceType};
 
 pub const EXTENSION: &str = ".meta.toml";
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct AssetMetadata {
     pub name: PathBuf,
     pub uuid: Uuid,
@@ -18,6 +20,7 @@ pub struct AssetMetadata {
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct ResourceMetadata {
     #[serde(rename = "type")]
     pub tp: ResourceType,
@@ -33,39 +36,38 @@ impl ResourceMetadata {
             uuid: Uuid::new_v4(),
         }
     }
+
+    /// Creates a resource with the UUID derived from the UUID of asset and the resource name,
+    /// which is used in reproducible builds.
+    pub fn derive(asset: &Uuid, name: PathBuf, tp: ResourceType) -> Self {
+        ResourceMetadata {
+            tp: tp,
+            uuid: Uuid::new_v5(asset, key(&name).as_bytes()),
+            name: name,
+        }
+    }
 }
 
 impl AssetMetadata {
-    pub fn import_from<T: AsRef<Path>>(assets_dir: T, filename: T) -> Result<AssetMetadata> {
+    pub fn import_from<T: AsRef<Path>>(mounts: &Mounts, filename: T) -> Result<AssetMetadata> {
         let filename = filename.as_ref();
-        let file = assets_dir.as_ref().join(filename);
+        let file = mounts.join(filename);
         let metafile = Self::with_extension(&file);
 
         if metafile.exists() {
-            let bytes = fs::read(&metafile).unwrap();
-            match toml::de::from_slice::<AssetMetadata>(&bytes) {
+            let source = fs::read_to_string(&metafile)?;
+            match toml::de::from_str::<AssetMetadata>(&source) {
                 Ok(mut metadata) => {
-                    let name = filename.to_owned();
-                    if name != metadata.name {
-                        for v in &mut metadata.resources {
-                            if v.name == metadata.name {
-                                v.name = name.clone();
-                            }
-                        }
-
-                        metadata.name = name;
-                    }
-
-                    metadata.name = filename.to_owned();
+                    metadata.rename(filename);
                     return Ok(metadata);
                 }
-                Err(err) => {
-                    warn!(
-                        "A meta data file ({:?}) seems broken. {}",
-                        filename.display(),
-                        err
-                    );
-                }
+                // Keeps the broken meta-file instead of generating a new one, which would
+                // change its UUIDs and lose all the references to it.
+                Err(err) => bail!(
+                    "Meta-file of {:?} is broken, fix or remove it to generate a new one. {}",
+                    filename.display(),
+                    utils::toml_error(&source, &err)
+                ),
             }
         }
 
@@ -81,8 +83,27 @@ impl AssetMetadata {
         Ok(metadata)
     }
 
-    pub fn save<T: AsRef<Path>>(&self, assets_dir: T, filename: T) -> Result<()> {
-        let metafile = Self::with_extension(&assets_dir.as_ref().join(filename.as_ref()));
+    /// Derives the UUID of asset from its name, which is used in reproducible builds.
+    pub fn derive_uuid<T: AsRef<Path>>(filename: T) -> Uuid {
+        Uuid::new_v5(&Uuid::NAMESPACE_URL, key(filename.as_ref()).as_bytes())
+    }
+
+    /// Renames the asset, and the resource that named after it.
+    pub fn rename<T: AsRef<Path>>(&mut self, filename: T) {
+        let name = filename.as_ref().to_owned();
+        if name != self.name {
+            for v in &mut self.resources {
+                if v.name == self.name {
+                    v.name = name.clone();
+                }
+            }
+
+            self.name = name;
+        }
+    }
+
+    pub fn save<T: AsRef<Path>>(&self, mounts: &Mounts, filename: T) -> Result<()> {
+        let metafile = Self::with_extension(&mounts.join(filename));
         let contents = toml::ser::to_string_pretty(self).unwrap();
         fs::write(metafile, contents)?;
         Ok(())
@@ -97,3 +118,13 @@ impl AssetMetadata {
         }
     }
 }
+
+/// Joins the components of name with `/`, so the derived UUIDs are the same on all platforms.
+fn key(name: &Path) -> String {
+    let names: Vec<_> = name
+        .components()
+        .map(|v| v.as_os_str().to_string_lossy().into_owned())
+        .collect();
+
+    names.join("/")
+}
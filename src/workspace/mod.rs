@@ -1,29 +1,96 @@
 pub mod cache;
 pub mod settings;
+pub mod storage;
 pub mod utils;
 
+pub mod mounts;
+pub mod pattern;
+pub use self::mounts::Mounts;
+
 pub mod metadata;
 pub use self::metadata::AssetMetadata;
 
 pub mod database;
 pub use self::database::AssetDatabase;
 
-use std::fs;
-use std::path::Path;
+pub mod maintenance;
+pub mod report;
+
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::time::Duration;
 
-use failure::ResultExt;
-use toml;
+use notify::{self, DebouncedEvent, RecursiveMode, Watcher};
 
-use platform::RuntimePlatform;
+use self::cache::CheckMode;
+use self::settings::ProfileSettings;
+use platform::{Compression, RuntimePlatform};
 
 pub type Result<T> = ::std::result::Result<T, ::failure::Error>;
 
-pub struct Workspace {
-    database: AssetDatabase,
+/// The delay in milliseconds that file events are collected before rebuilding, which merges
+/// the bursts of events produced by editors and version control tools.
+const WATCH_DELAY: u64 = 500;
+
+/// The options of building workspace.
+#[derive(Debug, Clone)]
+pub struct Options {
+    /// The platform that resources are built for.
+    pub platform: RuntimePlatform,
+    /// The way to detect modifications of files.
+    pub check: CheckMode,
+    /// The number of assets that are compiled concurrently, 0 means the number of logical CPUs.
+    pub jobs: usize,
+    /// The folder of intermediate files, which overrides the one in workspace.toml.
+    pub cache_dir: Option<PathBuf>,
+    /// Keeps building the other assets when an asset fails, and reports all the failures at
+    /// the end.
+    pub keep_going: bool,
+    /// The compression of textures and audios, which overrides the ones in meta-files.
+    pub compression: Option<Compression>,
+    /// Derives the UUIDs of new assets and resources from their names instead of generating
+    /// random ones, so clean builds of the same workspace produce identical resources.
+    pub reproducible: bool,
+    /// The name of build profile in workspace.toml, which overrides the options above.
+    pub profile: Option<String>,
 }
 
-impl Workspace {
-    pub fn new<T: AsRef<Path>>(root: T, platform: RuntimePlatform, strict: bool) -> Result<Self> {
+impl Options {
+    fn apply(&mut self, profile: &ProfileSettings) {
+        if let Some(v) = profile.platform {
+            self.platform = v;
+        }
+
+        if let Some(v) = profile.compression {
+            self.compression = Some(v);
+        }
+
+        // Turning strict off falls back to the default check mode, unless another non-strict
+        // mode is selected already.
+        match profile.strict {
+            Some(true) => self.check = CheckMode::Strict,
+            Some(false) if self.check == CheckMode::Strict => self.check = CheckMode::Combined,
+            _ => {}
+        }
+
+        if let Some(v) = profile.reproducible {
+            self.reproducible = v;
+        }
+    }
+}
+
+/// The settings and resolved folders of workspace.
+pub struct Layout {
+    pub root: PathBuf,
+    pub settings: settings::Settings,
+    pub mounts: Mounts,
+    pub resources_dir: PathBuf,
+    pub cache_dir: PathBuf,
+}
+
+impl Layout {
+    pub fn open<T: AsRef<Path>>(root: T, options: &Options) -> Result<Self> {
         // Gets canonicalized and absolute path to root folder.
         let mut root = root.as_ref().to_owned();
         if !root.is_absolute() {
@@ -45,19 +112,323 @@ impl Workspace {
             );
         }
 
-        let params: settings::Settings = toml::de::from_str(&fs::read_to_string(&file).unwrap())
-            .context("The configs file workspace.toml is broken.")?;
+        let mut params = settings::Settings::load(&file)?;
+        if let Some(ref v) = options.profile {
+            if let Some(ref destination) = params.profile(v)?.destination {
+                params.assets.destination = destination.clone();
+            }
+        }
+
+        let cache_dir = match options.cache_dir {
+            Some(ref v) if v.is_absolute() => v.clone(),
+            Some(ref v) => ::std::env::current_dir()?.join(v),
+            None => root.join(&params.cache.dir),
+        };
+
+        let layout = Layout {
+            mounts: Mounts::new(&root, &params.assets)?,
+            resources_dir: utils::resolve(root.join(&params.assets.destination)),
+            cache_dir: utils::resolve(cache_dir),
+            settings: params,
+            root: root,
+        };
+
+        layout.validate()?;
+        Ok(layout)
+    }
+
+    /// Checks that the resources and intermediates folders never contain the workspace or
+    /// assets, since they are removed by `clean`.
+    fn validate(&self) -> Result<()> {
+        if self.root.starts_with(&self.resources_dir) {
+            bail!("The resources folder can not contain the workspace.");
+        }
+
+        if self.root.starts_with(&self.cache_dir) {
+            bail!("The intermediates folder can not contain the workspace.");
+        }
+
+        for &(_, ref dir) in self.mounts.dirs() {
+            if self.resources_dir.starts_with(dir) || dir.starts_with(&self.resources_dir) {
+                bail!("The assets folder can not be located under resources folder.");
+            }
+
+            if self.cache_dir.starts_with(dir) {
+                bail!("The intermediates folder can not be located under assets folder.");
+            }
+        }
+
+        if self.cache_dir.starts_with(&self.resources_dir) {
+            bail!("The intermediates folder can not be located under resources folder.");
+        }
+
+        Ok(())
+    }
+}
+
+pub struct Workspace {
+    database: AssetDatabase,
+}
+
+impl Workspace {
+    pub fn new<T: AsRef<Path>>(root: T, options: Options) -> Result<Self> {
+        let layout = Layout::open(root, &options)?;
 
-        let workspace = Workspace {
-            database: AssetDatabase::new(&root, params.assets, platform, strict)?,
+        let mut options = options;
+        if let Some(v) = options.profile.clone() {
+            info!("Builds with profile {}.", v);
+            options.apply(layout.settings.profile(&v)?);
+        }
+
+        let storage = match layout.settings.cache.shared {
+            Some(ref v) => Some(storage::open(&layout.root, v)?),
+            None => None,
         };
 
-        Ok(workspace)
+        let database = AssetDatabase::new(
+            &layout.root,
+            layout.settings.assets,
+            &layout.cache_dir,
+            storage,
+            &options,
+        )?;
+
+        Ok(Workspace { database: database })
     }
 
     pub fn import_all(&mut self) -> Result<()> {
         self.database.import_all()
     }
+
+    /// Gets the report of last build.
+    pub fn report(&self) -> report::BuildReport {
+        self.database.report()
+    }
+
+    /// Watches the assets folder, and re-imports the assets incrementally whenever they are
+    /// created, modified or removed. This blocks until the watcher is disconnected.
+    pub fn watch(&mut self) -> Result<()> {
+        let (tx, rx) = mpsc::channel();
+        let mut watcher = notify::watcher(tx, Duration::from_millis(WATCH_DELAY))?;
+        for &(_, ref dir) in self.database.mounts().dirs() {
+            watcher.watch(dir, RecursiveMode::Recursive)?;
+            info!("Watching {:?} for changes.", dir);
+        }
+
+        loop {
+            let mut names = BTreeSet::new();
+            let mut rescan = false;
+            let mut ignores = false;
+
+            // Blocks until the first event, and then drains the ones that arrived in the
+            // same batch.
+            let mut event = Some(rx.recv()?);
+            while let Some(v) = event {
+                match v {
+                    DebouncedEvent::Create(path)
+                    | DebouncedEvent::Write(path)
+                    | DebouncedEvent::Remove(path) => {
+                        ignores |= self.database.mounts().is_ignore_file(&path);
+                        names.extend(self.database.asset_name(path));
+                    }
+                    DebouncedEvent::Rename(from, to) => {
+                        ignores |= self.database.mounts().is_ignore_file(&from);
+                        ignores |= self.database.mounts().is_ignore_file(&to);
+                        names.extend(self.database.asset_name(from));
+                        names.extend(self.database.asset_name(to));
+                    }
+                    DebouncedEvent::Rescan => rescan = true,
+                    DebouncedEvent::Error(err, path) => {
+                        warn!("Failed to watch {:?}. {}", path, err);
+                    }
+                    _ => {}
+                }
+
+                event = rx.try_recv().ok();
+            }
+
+            // The `.crayonignore` files are never mapped to assets, and changing them could
+            // include or exclude any asset.
+            if ignores {
+                info!("Reloads the {} files.", mounts::IGNORE);
+                self.database.reload_ignores();
+            }
+
+            if rescan || ignores {
+                info!("Rescans the whole assets folder.");
+                if let Err(err) = self.database.rescan() {
+                    warn!("Failed to rebuild assets. {}", err);
+                }
+
+                continue;
+            }
+
+            if names.is_empty() {
+                continue;
+            }
+
+            // Refreshes the names in one pass, so the moved files could be matched with their
+            // orphaned meta-files.
+            info!("Rebuilds {:?}.", names);
+            if let Err(err) = self.database.refresh(&names) {
+                warn!("Failed to rebuild {:?}. {}", names, err);
+            }
+        }
+    }
 }
 
 impl Workspace {}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::fs;
+    use std::process;
+
+    use hound;
+    use walkdir::WalkDir;
+
+    use super::*;
+
+    /// Creates a fresh workspace with a few assets in the temporary folder.
+    fn fixture(name: &str) -> PathBuf {
+        let root = ::std::env::temp_dir().join(format!("crayon-cli-{}-{}", process::id(), name));
+        if root.exists() {
+            fs::remove_dir_all(&root).unwrap();
+        }
+
+        fs::create_dir_all(root.join("assets/sounds")).unwrap();
+        fs::write(
+            root.join(settings::NAME),
+            "version = 2\n\n[assets]\nsource = 'assets'\ndestination = 'resources'\n\n[[assets.importers]]\ntype = 'Audio'\nextensions = ['.wav']\n",
+        ).unwrap();
+
+        fs::write(root.join("assets/a.txt"), "a").unwrap();
+        fs::write(root.join("assets/sounds/b.bin"), [0u8, 1, 2, 3]).unwrap();
+
+        let spec = hound::WavSpec {
+            channels: 1,
+            sample_rate: 8000,
+            bits_per_sample: 16,
+            sample_format: hound::SampleFormat::Int,
+        };
+
+        let mut writer = hound::WavWriter::create(root.join("assets/sounds/c.wav"), spec).unwrap();
+        for i in 0..8000 {
+            let v = (i as f32 * 440.0 * 2.0 * ::std::f32::consts::PI / 8000.0).sin();
+            writer.write_sample((v * 8000.0) as i16).unwrap();
+        }
+
+        writer.finalize().unwrap();
+        root
+    }
+
+    fn options() -> Options {
+        Options {
+            platform: RuntimePlatform::Macos,
+            check: CheckMode::Combined,
+            jobs: 0,
+            cache_dir: None,
+            keep_going: false,
+            compression: None,
+            reproducible: true,
+            profile: None,
+        }
+    }
+
+    /// Reads all the files in resources folder.
+    fn resources(root: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+        let dir = root.join("resources");
+        WalkDir::new(&dir)
+            .into_iter()
+            .map(|v| v.unwrap())
+            .filter(|v| v.file_type().is_file())
+            .map(|v| {
+                let name = v.path().strip_prefix(&dir).unwrap().to_owned();
+                (name, fs::read(v.path()).unwrap())
+            }).collect()
+    }
+
+    /// Builds the workspace, and reads all the files in its resources folder.
+    fn build(root: &Path) -> BTreeMap<PathBuf, Vec<u8>> {
+        Workspace::new(root, options()).unwrap().import_all().unwrap();
+        resources(root)
+    }
+
+    #[test]
+    fn reproducible() {
+        let a = fixture("reproducible-a");
+        let b = fixture("reproducible-b");
+
+        let resources = build(&a);
+        assert!(resources.contains_key(Path::new(".MANIFEST")));
+        assert_eq!(resources.len(), 4);
+        assert_eq!(resources, build(&b));
+
+        fs::remove_dir_all(a).unwrap();
+        fs::remove_dir_all(b).unwrap();
+    }
+
+    #[test]
+    fn layout() {
+        let root = fixture("layout");
+        let settings = fs::read_to_string(root.join(settings::NAME)).unwrap();
+
+        for v in &["'.'", "''", "'..'", "'assets/resources'"] {
+            let contents = settings.replace("'resources'", v);
+            fs::write(root.join(settings::NAME), contents).unwrap();
+            assert!(Layout::open(&root, &options()).is_err(), "{}", v);
+        }
+
+        fs::write(root.join(settings::NAME), settings).unwrap();
+        assert!(Layout::open(&root, &options()).is_ok());
+
+        for v in &[root.clone(), root.join(".."), root.join("assets/intermediates")] {
+            let mut options = options();
+            options.cache_dir = Some(v.clone());
+            assert!(Layout::open(&root, &options).is_err(), "{:?}", v);
+        }
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn corrupted() {
+        let root = fixture("corrupted");
+        let src = Path::new(env!("CARGO_MANIFEST_DIR")).join("src/assets/audio/fixtures/truncated.wav");
+        fs::copy(src, root.join("assets/sounds/truncated.wav")).unwrap();
+
+        // The corrupted file is skipped and reported, even if the build does not keep going.
+        let mut workspace = Workspace::new(&root, options()).unwrap();
+        let err = workspace.import_all().unwrap_err().to_string();
+        assert_eq!(err, "1 of 4 assets failed to build.");
+
+        let resources = resources(&root);
+        assert!(resources.contains_key(Path::new(".MANIFEST")));
+        assert_eq!(resources.len(), 4);
+        let mut caches = root.join("intermediates").read_dir().unwrap();
+        assert!(caches.any(|v| cache::is_cache_dir(&v.unwrap().path())));
+
+        fs::remove_dir_all(root).unwrap();
+    }
+
+    #[test]
+    fn ignore() {
+        let root = fixture("ignore");
+        let mut workspace = Workspace::new(&root, options()).unwrap();
+        workspace.import_all().unwrap();
+        assert_eq!(resources(&root).len(), 4);
+
+        // The patterns are only read again after reloading, like `watch` does when the file
+        // changes.
+        fs::write(root.join("assets").join(mounts::IGNORE), "sounds/\n").unwrap();
+        workspace.database.rescan().unwrap();
+        assert_eq!(resources(&root).len(), 4);
+
+        workspace.database.reload_ignores();
+        workspace.database.rescan().unwrap();
+        assert_eq!(resources(&root).len(), 2);
+
+        fs::remove_dir_all(root).unwrap();
+    }
+}
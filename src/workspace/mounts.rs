@@ -0,0 +1,206 @@
+//! The assets folders of workspace. Besides the main assets folder, other folders (like the
+//! assets shared by engine) could be mounted at virtual prefixes, so the assets from all of
+//! them live in one namespace.
+
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use super::pattern::PatternSet;
+use super::settings::AssetSettings;
+use super::utils;
+use super::Result;
+
+/// The file that lists the patterns of ignored files in assets folder, with the same syntax
+/// as `.gitignore`.
+pub const IGNORE: &str = ".crayonignore";
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct Mounts {
+    /// The prefixes and folders, the main assets folder comes first with an empty prefix.
+    dirs: Vec<(PathBuf, PathBuf)>,
+    /// The ignore patterns of each folder, relative to the folder.
+    #[serde(skip)]
+    ignores: Vec<PatternSet>,
+    /// The exclude patterns in workspace.toml, relative to the virtual root.
+    #[serde(skip)]
+    exclude: PatternSet,
+}
+
+impl Mounts {
+    pub fn new(root: &Path, params: &AssetSettings) -> Result<Self> {
+        let mut dirs = vec![(PathBuf::new(), Self::canonicalize(root, &params.source)?)];
+
+        for v in &params.mounts {
+            let prefix = match utils::normalize(&v.prefix) {
+                Some(ref p) if !p.as_os_str().is_empty() => p.clone(),
+                _ => bail!("The prefix {:?} of mounted folder is invalid.", v.prefix),
+            };
+
+            for &(ref p, _) in dirs.iter().skip(1) {
+                if p.starts_with(&prefix) || prefix.starts_with(p) {
+                    bail!("The prefix {:?} of mounted folder overlaps with {:?}.", prefix, p);
+                }
+            }
+
+            dirs.push((prefix, Self::canonicalize(root, &v.source)?));
+        }
+
+        let mut exclude = PatternSet::new();
+        for v in &params.exclude {
+            exclude.add(v);
+        }
+
+        let mut mounts = Mounts {
+            dirs: dirs,
+            ignores: Vec::new(),
+            exclude: exclude,
+        };
+
+        mounts.reload();
+        Ok(mounts)
+    }
+
+    /// Reads the `.crayonignore` files of folders again.
+    pub fn reload(&mut self) {
+        self.ignores = self
+            .dirs
+            .iter()
+            .map(|&(_, ref dir)| {
+                let mut patterns = PatternSet::new();
+                if let Ok(source) = fs::read_to_string(dir.join(IGNORE)) {
+                    patterns.add_lines(&source);
+                }
+
+                patterns
+            }).collect();
+    }
+
+    /// Checks if `path` is the `.crayonignore` file of any folder.
+    pub fn is_ignore_file<T: AsRef<Path>>(&self, path: T) -> bool {
+        let path = path.as_ref();
+        self.dirs.iter().any(|&(_, ref dir)| path == dir.join(IGNORE))
+    }
+
+    /// Resolves the real path of folder at `source` relative to workspace `root`, so it could
+    /// be compared with the canonicalized paths of files.
+    fn canonicalize(root: &Path, source: &Path) -> Result<PathBuf> {
+        let dir = root.join(source);
+        match dir.canonicalize() {
+            Ok(v) => Ok(v),
+            Err(_) => bail!("The assets folder ({:?}) is not exists.", dir),
+        }
+    }
+
+    /// Gets the main assets folder.
+    pub fn main(&self) -> &Path {
+        &self.dirs[0].1
+    }
+
+    /// Gets the prefixes and folders that mounted.
+    pub fn dirs(&self) -> &[(PathBuf, PathBuf)] {
+        &self.dirs
+    }
+
+    /// Resolves the path to file with virtual `name`.
+    pub fn join<T: AsRef<Path>>(&self, name: T) -> PathBuf {
+        let name = name.as_ref();
+        let (ref prefix, ref dir) = self.dirs[self.index(name)];
+        dir.join(name.strip_prefix(prefix).unwrap())
+    }
+
+    /// Checks if the file with virtual `name` is ignored by `.crayonignore` of its folder or
+    /// excluded in workspace.toml.
+    pub fn excluded<T: AsRef<Path>>(&self, name: T) -> bool {
+        let name = name.as_ref();
+        if self.exclude.matches(name) {
+            return true;
+        }
+
+        let i = self.index(name);
+        match self.ignores.get(i) {
+            Some(v) => v.matches(name.strip_prefix(&self.dirs[i].0).unwrap()),
+            None => false,
+        }
+    }
+
+    /// Gets the index of folder that contains the file with virtual `name`.
+    fn index(&self, name: &Path) -> usize {
+        for (i, &(ref prefix, _)) in self.dirs.iter().enumerate().skip(1) {
+            if name.starts_with(prefix) {
+                return i;
+            }
+        }
+
+        0
+    }
+
+    /// Gets the virtual name of file at `path`. Returns `None` if it is not located in any of
+    /// the mounted folders.
+    pub fn name<T: AsRef<Path>>(&self, path: T) -> Option<PathBuf> {
+        let path = path.as_ref();
+
+        // Prefers the deepest folder, since a folder could be mounted inside another one.
+        self.dirs
+            .iter()
+            .filter(|&&(_, ref dir)| path.starts_with(dir))
+            .max_by_key(|&&(_, ref dir)| dir.components().count())
+            .map(|&(ref prefix, ref dir)| prefix.join(path.strip_prefix(dir).unwrap()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process;
+
+    use toml;
+
+    use super::*;
+
+    #[test]
+    fn parent() {
+        let dir = ::std::env::temp_dir().join(format!("crayon-cli-{}-mounts", process::id()));
+        fs::create_dir_all(dir.join("game/assets")).unwrap();
+        fs::create_dir_all(dir.join("engine/assets/shaders")).unwrap();
+
+        let params: AssetSettings = toml::de::from_str(
+            "source = 'assets'\ndestination = 'resources'\nimporters = []\n\n[[mounts]]\nsource = '../engine/assets'\nprefix = 'engine'\n",
+        ).unwrap();
+
+        // The paths of files are usually canonicalized, which never contain `..`.
+        let root = dir.join("game").canonicalize().unwrap();
+        let mounts = Mounts::new(&root, &params).unwrap();
+        let path = dir.canonicalize().unwrap().join("engine/assets/shaders/a.glsl");
+        assert_eq!(mounts.name(&path), Some(PathBuf::from("engine/shaders/a.glsl")));
+        assert_eq!(mounts.join("engine/shaders/a.glsl"), path);
+
+        let params: AssetSettings = toml::de::from_str(
+            "source = 'assets'\ndestination = 'resources'\nimporters = []\n\n[[mounts]]\nsource = '../missing'\nprefix = 'engine'\n",
+        ).unwrap();
+
+        assert!(Mounts::new(&root, &params).is_err());
+        fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn ignore() {
+        let dir = ::std::env::temp_dir().join(format!("crayon-cli-{}-mounts-ignore", process::id()));
+        fs::create_dir_all(dir.join("assets/sub")).unwrap();
+
+        let params: AssetSettings =
+            toml::de::from_str("source = 'assets'\ndestination = 'resources'\nimporters = []\n").unwrap();
+
+        let root = dir.canonicalize().unwrap();
+        let mut mounts = Mounts::new(&root, &params).unwrap();
+        assert!(!mounts.excluded("a.psd"));
+        assert!(mounts.is_ignore_file(root.join("assets").join(IGNORE)));
+        assert!(!mounts.is_ignore_file(root.join("assets/sub").join(IGNORE)));
+
+        fs::write(root.join("assets").join(IGNORE), "*.psd\n").unwrap();
+        assert!(!mounts.excluded("a.psd"));
+        mounts.reload();
+        assert!(mounts.excluded("a.psd"));
+        assert!(mounts.excluded("sub/a.psd"));
+
+        fs::remove_dir_all(dir).unwrap();
+    }
+}
@@ -0,0 +1,278 @@
+//! Glob patterns that match the names of assets, with the same syntax as `.gitignore`.
+//!
+//! A `*` matches anything except `/`, a `?` matches any single character except `/`, and
+//! `[a-z]` matches one character in the range. A `**` component matches any number of
+//! folders. Patterns without a `/` in the middle match the file or folder name at any depth,
+//! and patterns ending with `/` only match folders. A pattern that matches a folder matches
+//! everything under it too.
+
+use std::path::Path;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Pattern {
+    glob: String,
+    components: Vec<Vec<char>>,
+    dir_only: bool,
+}
+
+impl Pattern {
+    pub fn new(glob: &str) -> Self {
+        let mut v = glob.trim();
+        let dir_only = v.ends_with('/');
+        v = v.trim_right_matches('/');
+
+        // Patterns with separator are relative to the root, the others match at any depth.
+        let anchored = v.contains('/');
+        v = v.trim_left_matches('/');
+
+        let mut components: Vec<Vec<char>> = Vec::new();
+        if !anchored {
+            components.push("**".chars().collect());
+        }
+
+        for c in v.split('/').filter(|c| !c.is_empty()) {
+            components.push(c.chars().collect());
+        }
+
+        Pattern {
+            glob: glob.to_owned(),
+            components: components,
+            dir_only: dir_only,
+        }
+    }
+
+    /// Gets the source of this pattern.
+    pub fn as_str(&self) -> &str {
+        &self.glob
+    }
+
+    /// Checks if the pattern matches `name` or any of its parent folders.
+    pub fn matches<T: AsRef<Path>>(&self, name: T) -> bool {
+        let names: Vec<String> = name
+            .as_ref()
+            .components()
+            .map(|v| v.as_os_str().to_string_lossy().into_owned())
+            .collect();
+
+        for i in 1..names.len() + 1 {
+            // The last component is a file, which is never matched by folder-only patterns.
+            if self.dir_only && i == names.len() {
+                break;
+            }
+
+            if matches_components(&self.components, &names[..i]) {
+                return true;
+            }
+        }
+
+        false
+    }
+}
+
+/// A list of patterns where the later ones take precedence, and the ones prefixed with `!`
+/// re-include the names that excluded by previous patterns.
+#[derive(Debug, Clone, Default)]
+pub struct PatternSet {
+    patterns: Vec<(Pattern, bool)>,
+}
+
+impl PatternSet {
+    pub fn new() -> Self {
+        PatternSet::default()
+    }
+
+    /// Adds the patterns in `source`, one per line. Empty lines and the lines starting with
+    /// `#` are skipped.
+    pub fn add_lines(&mut self, source: &str) {
+        for line in source.lines() {
+            let line = line.trim();
+            if !line.is_empty() && !line.starts_with('#') {
+                self.add(line);
+            }
+        }
+    }
+
+    pub fn add(&mut self, glob: &str) {
+        if glob.starts_with('!') {
+            self.patterns.push((Pattern::new(&glob[1..]), false));
+        } else {
+            self.patterns.push((Pattern::new(glob), true));
+        }
+    }
+
+    /// Checks if `name` is matched by the last pattern that applies to it.
+    pub fn matches<T: AsRef<Path>>(&self, name: T) -> bool {
+        let name = name.as_ref();
+        self.patterns
+            .iter()
+            .rev()
+            .find(|v| v.0.matches(name))
+            .map(|v| v.1)
+            .unwrap_or(false)
+    }
+}
+
+fn matches_components(pattern: &[Vec<char>], names: &[String]) -> bool {
+    match pattern.first() {
+        None => names.is_empty(),
+        Some(p) if p.iter().collect::<String>() == "**" => {
+            (0..names.len() + 1).any(|i| matches_components(&pattern[1..], &names[i..]))
+        }
+        Some(p) => {
+            if names.is_empty() {
+                return false;
+            }
+
+            let name: Vec<char> = names[0].chars().collect();
+            matches_chars(p, &name) && matches_components(&pattern[1..], &names[1..])
+        }
+    }
+}
+
+fn matches_chars(pattern: &[char], name: &[char]) -> bool {
+    match pattern.first() {
+        None => name.is_empty(),
+        Some('*') => (0..name.len() + 1).any(|i| matches_chars(&pattern[1..], &name[i..])),
+        Some('?') => !name.is_empty() && matches_chars(&pattern[1..], &name[1..]),
+        Some('[') => {
+            if name.is_empty() {
+                return false;
+            }
+
+            match matches_class(&pattern[1..], name[0]) {
+                Some((true, len)) => matches_chars(&pattern[len + 1..], &name[1..]),
+                Some((false, _)) => false,
+                // Treats the unclosed bracket as a literal.
+                None => name[0] == '[' && matches_chars(&pattern[1..], &name[1..]),
+            }
+        }
+        Some('\\') if pattern.len() > 1 => {
+            !name.is_empty() && name[0] == pattern[1] && matches_chars(&pattern[2..], &name[1..])
+        }
+        Some(&c) => !name.is_empty() && name[0] == c && matches_chars(&pattern[1..], &name[1..]),
+    }
+}
+
+/// Matches `c` against the character class after `[`. Returns whether it matches and the
+/// length of class including the closing `]`, or `None` if the class is not closed.
+fn matches_class(pattern: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 0;
+    let negated = match pattern.first() {
+        Some('!') | Some('^') => {
+            i += 1;
+            true
+        }
+        _ => false,
+    };
+
+    let mut matched = false;
+    let mut first = true;
+    while i < pattern.len() {
+        if pattern[i] == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+
+        if i + 2 < pattern.len() && pattern[i + 1] == '-' && pattern[i + 2] != ']' {
+            matched |= pattern[i] <= c && c <= pattern[i + 2];
+            i += 3;
+        } else {
+            matched |= pattern[i] == c;
+            i += 1;
+        }
+
+        first = false;
+    }
+
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pattern() {
+        let cases = [
+            // Patterns without separator match at any depth.
+            ("*.png", "a.png", true),
+            ("*.png", "textures/ui/a.png", true),
+            ("*.png", "a.jpg", false),
+            ("*.png", "a.png/b.txt", true),
+            // Patterns with separator are relative to the root.
+            ("textures/*.png", "textures/a.png", true),
+            ("textures/*.png", "textures/ui/a.png", false),
+            ("textures/*.png", "ui/textures/a.png", false),
+            ("/a.png", "a.png", true),
+            ("/a.png", "ui/a.png", false),
+            // `*` and `?` never match separators.
+            ("a*", "a/b", true),
+            ("a?c", "abc", true),
+            ("a?c", "ac", false),
+            ("a*c", "a/c", false),
+            // `**` matches any number of folders.
+            ("**/ui/*.png", "ui/a.png", true),
+            ("**/ui/*.png", "textures/ui/a.png", true),
+            ("textures/**/a.png", "textures/a.png", true),
+            ("textures/**/a.png", "textures/x/y/a.png", true),
+            ("textures/**", "textures/x/a.png", true),
+            ("textures/**", "audio/a.ogg", false),
+            // Folder-only patterns match folders and everything under them, but not files.
+            ("wip/", "wip/a.png", true),
+            ("wip/", "textures/wip/a.png", true),
+            ("wip/", "wip", false),
+            ("wip/", "textures/wip", false),
+            // Character classes.
+            ("[ab].png", "a.png", true),
+            ("[ab].png", "c.png", false),
+            ("[a-c].png", "b.png", true),
+            ("[a-c].png", "d.png", false),
+            ("[!a-c].png", "d.png", true),
+            ("[^a-c].png", "b.png", false),
+            ("[]].png", "].png", true),
+            ("[a-].png", "-.png", true),
+            // Unclosed brackets and escapes are literals.
+            ("[a.png", "[a.png", true),
+            ("[a.png", "a.png", false),
+            ("\\*.png", "*.png", true),
+            ("\\*.png", "a.png", false),
+        ];
+
+        for &(glob, name, expected) in &cases {
+            assert_eq!(
+                Pattern::new(glob).matches(name),
+                expected,
+                "{:?} matches {:?}",
+                glob,
+                name
+            );
+        }
+    }
+
+    #[test]
+    fn pattern_set() {
+        let mut set = PatternSet::new();
+        set.add_lines("# comment\n\n*.psd\nwip/\n!wip/keep.png\n!important.psd\n");
+
+        let cases = [
+            ("a.psd", true),
+            ("textures/a.psd", true),
+            ("important.psd", false),
+            ("textures/important.psd", false),
+            ("wip/a.png", true),
+            ("wip/keep.png", false),
+            ("textures/wip/keep.png", true),
+            ("a.png", false),
+            ("# comment", false),
+        ];
+
+        for &(name, expected) in &cases {
+            assert_eq!(set.matches(name), expected, "{:?}", name);
+        }
+
+        // The later patterns take precedence.
+        let mut set = PatternSet::new();
+        set.add("!*.png");
+        set.add("*.png");
+        assert!(set.matches("a.png"));
+    }
+}
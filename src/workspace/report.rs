@@ -0,0 +1,99 @@
+//! The machine-readable report of build, which could be used to track the size and build time
+//! of assets.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde_json;
+
+use super::Result;
+
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[serde(rename_all = "lowercase")]
+pub enum AssetStatus {
+    /// The asset has been compiled and imported.
+    Rebuilt,
+    /// The outputs of asset have been restored from shared cache.
+    Restored,
+    /// The asset is up to date.
+    Skipped,
+    /// The asset could not be built.
+    Failed,
+}
+
+#[derive(Serialize, Debug, Clone)]
+pub struct AssetReport {
+    pub name: PathBuf,
+    pub status: AssetStatus,
+    /// The reason of rebuilding.
+    pub reason: Option<String>,
+    /// The time spent on this asset in seconds.
+    pub duration: f64,
+    /// The size in bytes of asset file and its dependencies.
+    pub input_size: u64,
+    /// The size in bytes of generated resources.
+    pub output_size: u64,
+    /// The formats that chosen by importer.
+    pub formats: Vec<String>,
+    pub warnings: Vec<String>,
+    pub errors: Vec<String>,
+}
+
+impl AssetReport {
+    pub fn new<T: AsRef<Path>>(name: T) -> Self {
+        AssetReport {
+            name: name.as_ref().to_owned(),
+            status: AssetStatus::Skipped,
+            reason: None,
+            duration: 0.0,
+            input_size: 0,
+            output_size: 0,
+            formats: Vec::new(),
+            warnings: Vec::new(),
+            errors: Vec::new(),
+        }
+    }
+}
+
+#[derive(Serialize, Debug, Clone, Default)]
+pub struct BuildReport {
+    pub platform: String,
+    pub rebuilt: usize,
+    pub restored: usize,
+    pub skipped: usize,
+    pub failed: usize,
+    /// The total time spent on assets in seconds, which might be longer than the wall time
+    /// since assets are built concurrently.
+    pub duration: f64,
+    pub assets: Vec<AssetReport>,
+}
+
+impl BuildReport {
+    pub fn new(platform: String, assets: &BTreeMap<PathBuf, AssetReport>) -> Self {
+        let mut report = BuildReport {
+            platform: platform,
+            ..Default::default()
+        };
+
+        for v in assets.values() {
+            match v.status {
+                AssetStatus::Rebuilt => report.rebuilt += 1,
+                AssetStatus::Restored => report.restored += 1,
+                AssetStatus::Skipped => report.skipped += 1,
+                AssetStatus::Failed => report.failed += 1,
+            }
+
+            report.duration += v.duration;
+            report.assets.push(v.clone());
+        }
+
+        report
+    }
+
+    /// Writes the report into file in JSON.
+    pub fn save<T: AsRef<Path>>(&self, path: T) -> Result<()> {
+        fs::write(path, serde_json::to_string_pretty(self)?)?;
+        Ok(())
+    }
+}
@@ -1,24 +1,329 @@
-use std::path::PathBuf;
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
 
-use assets::AssetType;
+use toml;
+
+use super::utils;
+use super::Result;
+use assets::{AssetParams, AssetType};
+use platform::{Compression, RuntimePlatform};
 
 pub const NAME: &str = "workspace.toml";
 
+/// The version of settings layout. The files without `version` key are written before
+/// versioning, and treated as version 0. Version 2 adds mounted folders and profiles.
+pub const VERSION: u32 = 2;
+
+/// The default settings that generated by `init` sub-command.
+pub const TEMPLATE: &str = r#"version = 2
+
+[assets]
+source = 'assets' # the path to assets folder.
+destination = 'resources' # the path to resources folder, which are usually compiled from assets.
+
+# sets the importer for extensions, if extensions are not listed below, it will be treated as
+# `Bytes` asset.
+[[assets.importers]]
+type = 'Texture'
+extensions = ['.png', '.jpg', '.jpeg', '.bmp', '.tga', '.psd']
+
+[[assets.importers]]
+type = 'Transmission'
+extensions = ['.obj', '.blend', '.fbx', '.gltf', '.dae', '.3ds']
+
+[[assets.importers]]
+type = 'Audio'
+extensions = ['.mp3', '.wav', '.ogg', '.flac']
+
+[cache]
+dir = 'intermediates' # the path to intermediates folder, relative to workspace.
+"#;
+
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct Settings {
+    #[serde(default)]
+    pub version: u32,
     pub assets: AssetSettings,
+    #[serde(default)]
+    pub cache: CacheSettings,
+    /// The named build profiles, which are selected with `--profile`.
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ProfileSettings>,
+}
+
+impl Settings {
+    /// Loads settings from file, and migrates it from older layouts.
+    pub fn load<T: AsRef<Path>>(file: T) -> Result<Self> {
+        let source = fs::read_to_string(file)?;
+
+        let value: toml::Value = match toml::de::from_str(&source) {
+            Ok(v) => v,
+            Err(err) => bail!("workspace.toml is broken. {}", utils::toml_error(&source, &err)),
+        };
+
+        let version = value
+            .get("version")
+            .and_then(|v| v.as_integer())
+            .unwrap_or(0);
+
+        if version < 0 || version > i64::from(VERSION) {
+            bail!(
+                "workspace.toml has version {}, but only version {} and older are supported.",
+                version,
+                VERSION
+            );
+        }
+
+        let mut settings: Settings = match toml::de::from_str(&source) {
+            Ok(v) => v,
+            Err(err) => bail!("workspace.toml is broken. {}", utils::toml_error(&source, &err)),
+        };
+
+        for v in &settings.assets.defaults {
+            v.validate(&source)?;
+        }
+
+        settings.migrate();
+        Ok(settings)
+    }
+
+    fn migrate(&mut self) {
+        // Version 0 has the same keys as version 1, which only adds `version` itself.
+        if self.version == 0 {
+            warn!(
+                "workspace.toml has no version, please add `version = {}` at the top of it.",
+                VERSION
+            );
+
+            self.version = 1;
+        }
+
+        // Version 2 only adds optional keys.
+        if self.version == 1 {
+            self.version = 2;
+        }
+    }
+
+    /// Gets the profile with `name`.
+    pub fn profile(&self, name: &str) -> Result<&ProfileSettings> {
+        match self.profiles.get(name) {
+            Some(v) => Ok(v),
+            None => bail!("Can not find profile {:?} in workspace.toml.", name),
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct AssetSettings {
     pub source: PathBuf,
     pub destination: PathBuf,
     pub importers: Vec<AssetExtensions>,
+    /// The other assets folders that mounted at virtual prefixes.
+    #[serde(default)]
+    pub mounts: Vec<MountSettings>,
+    /// The rules that seed the params of new meta-files.
+    #[serde(default)]
+    pub defaults: Vec<DefaultParams>,
+    /// The patterns of assets that are never imported, which are added after the ones in
+    /// `.crayonignore` of assets folders.
+    #[serde(default)]
+    pub exclude: Vec<String>,
+}
+
+/// The default params of assets whose names match `pattern`. The fields in `params` override
+/// the defaults of importer, and the rule is skipped if its `type` differs from the importer.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct DefaultParams {
+    pub pattern: String,
+    pub params: toml::value::Table,
+}
+
+impl DefaultParams {
+    /// Overrides the fields of `params` with the ones of this rule. Returns `None` if the rule
+    /// has a different `type`.
+    pub fn apply(&self, params: &AssetParams) -> Option<::std::result::Result<AssetParams, toml::de::Error>> {
+        let mut table = match toml::Value::try_from(params) {
+            Ok(toml::Value::Table(v)) => v,
+            _ => return None,
+        };
+
+        if self.params.get("type").map_or(false, |v| Some(v) != table.get("type")) {
+            return None;
+        }
+
+        for (k, v) in &self.params {
+            table.insert(k.clone(), v.clone());
+        }
+
+        Some(toml::Value::Table(table).try_into())
+    }
+
+    /// Checks that the rule fits the params of at least one importer, so mistakes are reported
+    /// when workspace.toml is loaded instead of silently skipping the rule.
+    fn validate(&self, source: &str) -> Result<()> {
+        let params = [
+            AssetParams::Texture(Default::default()),
+            AssetParams::Transmission(Default::default()),
+            AssetParams::Audio(Default::default()),
+        ];
+
+        let mut error = None;
+        for v in &params {
+            match self.apply(v) {
+                Some(Ok(_)) => return Ok(()),
+                Some(Err(err)) => error = error.or(Some(err)),
+                None => {}
+            }
+        }
+
+        match error {
+            Some(err) => bail!(
+                "workspace.toml is broken. The default params of {:?} are invalid. {}",
+                self.pattern,
+                utils::toml_error(source, &err)
+            ),
+            None => bail!(
+                "workspace.toml is broken. The default params of {:?} have an unknown type.",
+                self.pattern
+            ),
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct MountSettings {
+    /// The path to assets folder, relative to the root of workspace.
+    pub source: PathBuf,
+    /// The prefix of assets in this folder, which is used in manifest too.
+    pub prefix: PathBuf,
+}
+
+/// The overrides of a named build profile.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct ProfileSettings {
+    /// The path to resources folder, relative to the root of workspace.
+    pub destination: Option<PathBuf>,
+    /// The platform that resources are built for.
+    pub platform: Option<RuntimePlatform>,
+    /// The compression of textures and audios, which overrides the ones in meta-files.
+    pub compression: Option<Compression>,
+    /// Always hashes the contents of files to detect modifications.
+    pub strict: Option<bool>,
+    /// Derives the UUIDs of new assets and resources from their names.
+    pub reproducible: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default, deny_unknown_fields)]
+pub struct CacheSettings {
+    /// The folder of intermediate files, relative to the root of workspace.
+    pub dir: PathBuf,
+    /// The shared content-addressed cache, which could be a `http://` endpoint or a local
+    /// folder relative to the root of workspace.
+    pub shared: Option<String>,
+}
+
+impl Default for CacheSettings {
+    fn default() -> Self {
+        CacheSettings {
+            dir: "intermediates".into(),
+            shared: None,
+        }
+    }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(deny_unknown_fields)]
 pub struct AssetExtensions {
     #[serde(rename = "type")]
     pub tp: AssetType,
     pub extensions: Vec<String>,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::process;
+
+    use super::*;
+
+    fn load(name: &str, defaults: &str) -> Result<Settings> {
+        let file = env::temp_dir().join(format!("crayon-cli-{}-{}.toml", process::id(), name));
+        fs::write(
+            &file,
+            format!(
+                "version = 2\n\n[assets]\nsource = 'assets'\ndestination = 'resources'\nimporters = []\n{}",
+                defaults
+            ),
+        ).unwrap();
+
+        let settings = Settings::load(&file);
+        fs::remove_file(&file).unwrap();
+        settings
+    }
+
+    #[test]
+    fn defaults() {
+        let settings = load(
+            "defaults",
+            "
+[[assets.defaults]]
+pattern = 'ui/**'
+params = { type = 'Texture', mipmap = false, compression = 'None' }
+
+[[assets.defaults]]
+pattern = 'sfx/**'
+params = { trim_silence = true }
+",
+        ).unwrap();
+
+        let rule = &settings.assets.defaults[1];
+        assert!(rule.apply(&AssetParams::Texture(Default::default())).unwrap().is_err());
+        match rule.apply(&AssetParams::Audio(Default::default())) {
+            Some(Ok(AssetParams::Audio(v))) => assert!(v.trim_silence),
+            v => panic!("{:?}", v),
+        }
+
+        let err = load(
+            "defaults-key",
+            "
+[[assets.defaults]]
+pattern = 'ui/**'
+params = { type = 'Texture', mipmaps = false }
+",
+        ).unwrap_err();
+
+        let msg = format!("{}", err);
+        assert!(msg.contains("\"ui/**\""), "{}", msg);
+        assert!(msg.contains("unknown field `mipmaps`"), "{}", msg);
+        assert!(msg.ends_with("at line 10, column 30"), "{}", msg);
+
+        let err = load(
+            "defaults-untyped",
+            "
+[[assets.defaults]]
+pattern = 'ui/**'
+params = { mipmaps = false }
+",
+        ).unwrap_err();
+
+        assert!(format!("{}", err).contains("unknown field `mipmaps`"));
+
+        let err = load(
+            "defaults-type",
+            "
+[[assets.defaults]]
+pattern = 'ui/**'
+params = { type = 'Image' }
+",
+        ).unwrap_err();
+
+        assert!(format!("{}", err).contains("unknown type"));
+    }
+}
@@ -0,0 +1,292 @@
+//! Content-addressed stores of build outputs, which could be shared between developers and
+//! build machines.
+
+use std::fs;
+use std::io::{Read, Write};
+use std::net::{TcpStream, ToSocketAddrs};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use super::utils;
+
+pub type Result<T> = ::std::result::Result<T, ::failure::Error>;
+
+/// The timeout in seconds of network operations.
+const TIMEOUT: u64 = 30;
+
+/// The timeout in seconds of connecting, which keeps an unreachable shared cache from stalling
+/// the build.
+const CONNECT_TIMEOUT: u64 = 5;
+
+/// The intermediates and resources of an asset, which are stored as one entry.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+pub struct Bundle {
+    pub intermediates: Vec<(PathBuf, Vec<u8>)>,
+    pub resources: Vec<(PathBuf, Vec<u8>)>,
+}
+
+/// A content-addressed store, where the entries are never modified once written.
+pub trait Storage: Send + Sync {
+    /// Gets the entry with `key`. Returns `None` if it does not exist.
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>>;
+
+    /// Puts the entry with `key`.
+    fn put(&self, key: &str, bytes: &[u8]) -> Result<()>;
+}
+
+/// Opens the store at `location`, which could be a `http://` endpoint or a local folder
+/// relative to workspace `root`.
+pub fn open(root: &Path, location: &str) -> Result<Box<Storage>> {
+    if location.starts_with("http://") {
+        Ok(Box::new(HttpStorage::new(location)?))
+    } else {
+        Ok(Box::new(LocalStorage::new(root.join(location))?))
+    }
+}
+
+/// A store in local folder, which could be located on a network drive.
+pub struct LocalStorage {
+    dir: PathBuf,
+}
+
+impl LocalStorage {
+    pub fn new<T: AsRef<Path>>(dir: T) -> Result<Self> {
+        let dir = dir.as_ref().to_owned();
+        if !dir.exists() {
+            fs::create_dir_all(&dir)?;
+        }
+
+        Ok(LocalStorage { dir: dir })
+    }
+
+    fn path(&self, key: &str) -> PathBuf {
+        // Spreads entries into sub-folders to keep the folders small.
+        self.dir.join(&key[..2]).join(key)
+    }
+}
+
+impl Storage for LocalStorage {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        let path = self.path(key);
+        if !path.exists() {
+            return Ok(None);
+        }
+
+        Ok(Some(fs::read(path)?))
+    }
+
+    fn put(&self, key: &str, bytes: &[u8]) -> Result<()> {
+        let path = self.path(key);
+        fs::create_dir_all(path.parent().unwrap())?;
+        utils::write_atomically(&path, bytes)?;
+        Ok(())
+    }
+}
+
+/// A store behind HTTP endpoint, which accepts `GET` and `PUT` requests at `<url>/<key>`.
+pub struct HttpStorage {
+    host: String,
+    addr: String,
+    path: String,
+}
+
+impl HttpStorage {
+    pub fn new(url: &str) -> Result<Self> {
+        if !url.starts_with("http://") {
+            bail!("Only http:// is supported by shared cache, but got {}.", url);
+        }
+
+        let url = &url["http://".len()..];
+        let (host, path) = match url.find('/') {
+            Some(i) => (&url[..i], &url[i..]),
+            None => (url, ""),
+        };
+
+        let addr = if host.contains(':') {
+            host.to_owned()
+        } else {
+            format!("{}:80", host)
+        };
+
+        Ok(HttpStorage {
+            host: host.to_owned(),
+            addr: addr,
+            path: path.trim_right_matches('/').to_owned(),
+        })
+    }
+
+    fn connect(&self) -> Result<TcpStream> {
+        let mut last = None;
+        for addr in self.addr.to_socket_addrs()? {
+            match TcpStream::connect_timeout(&addr, Duration::from_secs(CONNECT_TIMEOUT)) {
+                Ok(v) => return Ok(v),
+                Err(err) => last = Some(err),
+            }
+        }
+
+        match last {
+            Some(err) => bail!("Failed to connect to {}. {}", self.host, err),
+            None => bail!("Failed to resolve {}.", self.host),
+        }
+    }
+
+    fn request(&self, method: &str, key: &str, body: &[u8]) -> Result<(u32, Vec<u8>)> {
+        let mut stream = self.connect()?;
+        stream.set_read_timeout(Some(Duration::from_secs(TIMEOUT)))?;
+        stream.set_write_timeout(Some(Duration::from_secs(TIMEOUT)))?;
+
+        // Uses HTTP/1.0, so the server closes connection after response and never sends
+        // chunked body.
+        write!(
+            stream,
+            "{} {}/{} HTTP/1.0\r\nHost: {}\r\nContent-Length: {}\r\n\r\n",
+            method,
+            self.path,
+            key,
+            self.host,
+            body.len()
+        )?;
+
+        stream.write_all(body)?;
+        stream.flush()?;
+
+        let mut response = Vec::new();
+        stream.read_to_end(&mut response)?;
+
+        let end = match response.windows(4).position(|v| v == b"\r\n\r\n") {
+            Some(v) => v,
+            None => bail!("Malformed HTTP response from {}.", self.host),
+        };
+
+        let head = String::from_utf8_lossy(&response[..end]).into_owned();
+        let status = match head.split_whitespace().nth(1).and_then(|v| v.parse().ok()) {
+            Some(v) => v,
+            None => bail!("Malformed HTTP status line from {}.", self.host),
+        };
+
+        let mut body = response.split_off(end + 4);
+        for line in head.lines().skip(1) {
+            if let Some(i) = line.find(':') {
+                if line[..i].trim().eq_ignore_ascii_case("content-length") {
+                    if let Ok(len) = line[i + 1..].trim().parse() {
+                        if body.len() < len {
+                            bail!(
+                                "Truncated HTTP response from {}. ({} of {} bytes)",
+                                self.host,
+                                body.len(),
+                                len
+                            );
+                        }
+
+                        body.truncate(len);
+                    }
+                }
+            }
+        }
+
+        Ok((status, body))
+    }
+}
+
+impl Storage for HttpStorage {
+    fn get(&self, key: &str) -> Result<Option<Vec<u8>>> {
+        match self.request("GET", key, &[])? {
+            (200, body) => Ok(Some(body)),
+            (404, _) => Ok(None),
+            (status, _) => bail!("Failed to get {} from {}. (HTTP {})", key, self.host, status),
+        }
+    }
+
+    fn put(&self, key: &str, bytes: &[u8]) -> Result<()> {
+        match self.request("PUT", key, bytes)? {
+            (status, _) if status >= 200 && status < 300 => Ok(()),
+            (status, _) => bail!("Failed to put {} to {}. (HTTP {})", key, self.host, status),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::TcpListener;
+    use std::str;
+    use std::thread;
+
+    use super::*;
+
+    /// Serves the requests with a stand-in of HTTP store, which only keeps the entry `hit`,
+    /// and cuts the body of entry `short`. Returns the url of store.
+    fn serve(requests: usize) -> (String, thread::JoinHandle<Vec<(String, Vec<u8>)>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}/cache/", listener.local_addr().unwrap());
+
+        let handle = thread::spawn(move || {
+            let mut received = Vec::new();
+            for stream in listener.incoming().take(requests) {
+                let mut stream = stream.unwrap();
+
+                let mut request = Vec::new();
+                let mut buf = [0; 1024];
+                while !request.windows(4).any(|v| v == b"\r\n\r\n") {
+                    let len = stream.read(&mut buf).unwrap();
+                    assert!(len > 0, "Connection closed before the end of header.");
+                    request.extend_from_slice(&buf[..len]);
+                }
+
+                let end = request.windows(4).position(|v| v == b"\r\n\r\n").unwrap();
+                let head = str::from_utf8(&request[..end]).unwrap().to_owned();
+                let len: usize = head
+                    .lines()
+                    .filter(|v| v.starts_with("Content-Length: "))
+                    .map(|v| v["Content-Length: ".len()..].parse().unwrap())
+                    .next()
+                    .unwrap();
+
+                let mut body = request.split_off(end + 4);
+                while body.len() < len {
+                    let n = stream.read(&mut buf).unwrap();
+                    assert!(n > 0, "Connection closed before the end of body.");
+                    body.extend_from_slice(&buf[..n]);
+                }
+
+                let line = head.lines().next().unwrap().to_owned();
+                let response: &[u8] = match line.as_str() {
+                    "GET /cache/hit HTTP/1.0" => b"HTTP/1.0 200 OK\r\nContent-Length: 3\r\n\r\nhit",
+                    "GET /cache/short HTTP/1.0" => b"HTTP/1.0 200 OK\r\nContent-Length: 10\r\n\r\nshort",
+                    "PUT /cache/new HTTP/1.0" => b"HTTP/1.0 201 Created\r\nContent-Length: 0\r\n\r\n",
+                    _ => b"HTTP/1.0 404 Not Found\r\nContent-Length: 0\r\n\r\n",
+                };
+
+                stream.write_all(response).unwrap();
+                received.push((line, body));
+            }
+
+            received
+        });
+
+        (url, handle)
+    }
+
+    #[test]
+    fn http() {
+        let (url, handle) = serve(4);
+        let storage = HttpStorage::new(&url).unwrap();
+
+        assert_eq!(storage.get("hit").unwrap(), Some(b"hit".to_vec()));
+        assert_eq!(storage.get("miss").unwrap(), None);
+        storage.put("new", b"bytes").unwrap();
+
+        let err = storage.get("short").unwrap_err().to_string();
+        assert!(err.starts_with("Truncated HTTP response"), "{}", err);
+
+        let received = handle.join().unwrap();
+        assert_eq!(received[2], ("PUT /cache/new HTTP/1.0".to_owned(), b"bytes".to_vec()));
+    }
+
+    #[test]
+    fn unreachable() {
+        // Binds and drops a listener, so nothing listens on its port.
+        let addr = TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap();
+        let storage = HttpStorage::new(&format!("http://{}", addr)).unwrap();
+        assert!(storage.get("miss").is_err());
+    }
+}
@@ -1,6 +1,13 @@
 use std::env;
+use std::fs;
+use std::io;
 use std::path::{Component, Path, PathBuf};
 
+use toml;
+
+/// The extension of temporary files that written before being renamed into place.
+pub const TMP_EXTENSION: &str = ".tmp";
+
 pub fn canonicalize<T: AsRef<Path>>(path: T) -> PathBuf {
     let mut buf = PathBuf::new();
     for v in path.as_ref().components() {
@@ -15,6 +22,43 @@ pub fn canonicalize<T: AsRef<Path>>(path: T) -> PathBuf {
     buf
 }
 
+/// Normalizes a relative path by resolving `.` and `..` components. Returns `None` if it is
+/// absolute or points outside of its root.
+pub fn normalize<T: AsRef<Path>>(path: T) -> Option<PathBuf> {
+    let mut buf = PathBuf::new();
+    for v in path.as_ref().components() {
+        match v {
+            Component::RootDir | Component::Prefix(_) => return None,
+            Component::CurDir => continue,
+            Component::ParentDir => {
+                if !buf.pop() {
+                    return None;
+                }
+            }
+            Component::Normal(v) => buf.push(v),
+        }
+    }
+
+    Some(buf)
+}
+
+/// Resolves the `.` and `..` components of an absolute path without touching the file system,
+/// so it works for the folders that do not exist yet.
+pub fn resolve<T: AsRef<Path>>(path: T) -> PathBuf {
+    let mut buf = PathBuf::new();
+    for v in path.as_ref().components() {
+        match v {
+            Component::CurDir => continue,
+            Component::ParentDir => {
+                buf.pop();
+            }
+            _ => buf.push(v.as_os_str()),
+        }
+    }
+
+    buf
+}
+
 pub fn current_exe() -> PathBuf {
     let dir = env::current_exe().unwrap();
     dir.read_link().unwrap_or(dir)
@@ -24,3 +68,68 @@ pub fn current_exe_dir() -> PathBuf {
     let dir = env::current_exe().unwrap();
     dir.read_link().unwrap_or(dir).parent().unwrap().to_owned()
 }
+
+/// Describes the error of parsing toml `source` with the line and column where it happens.
+/// The errors reported by serde (like unknown or missing fields) carry no position, so the
+/// first line that defines the key named in message is used instead.
+pub fn toml_error(source: &str, err: &toml::de::Error) -> String {
+    if let Some((_, col)) = err.line_col() {
+        return format!("{}, column {}", err, col + 1);
+    }
+
+    let msg = format!("{}", err);
+    let key = if msg.starts_with("unknown field") {
+        msg.split('`').nth(1)
+    } else {
+        msg.rsplit("for key `")
+            .next()
+            .filter(|_| msg.contains("for key `"))
+            .and_then(|v| v.split('`').next())
+            .and_then(|v| v.rsplit('.').next())
+    };
+
+    let key = match key {
+        Some(v) if !v.is_empty() => v,
+        _ => return msg,
+    };
+
+    // The key could also be defined in an inline table, like `params = { key = 1 }`.
+    for (i, line) in source.lines().enumerate() {
+        for (col, _) in line.match_indices(key) {
+            let head = line[..col].trim_right_matches(char::is_whitespace);
+            if !head.is_empty() && !head.ends_with('{') && !head.ends_with(',') {
+                continue;
+            }
+
+            let rest = line[col + key.len()..].trim_left_matches(char::is_whitespace);
+            if rest.starts_with('=') || rest.starts_with('.') {
+                return format!("{} at line {}, column {}", msg, i + 1, col + 1);
+            }
+        }
+    }
+
+    msg
+}
+
+/// Writes `contents` into a temporary file next to `path`, and then renames it into place. So
+/// readers always see either the old or the new file, never a partially written one.
+pub fn write_atomically<T: AsRef<Path>>(path: T, contents: &[u8]) -> io::Result<()> {
+    let path = path.as_ref();
+    let tmp = tmp_path(path);
+    fs::write(&tmp, contents)?;
+    fs::rename(&tmp, path)
+}
+
+/// Copies `src` into a temporary file next to `dst`, and then renames it into place.
+pub fn copy_atomically<T1: AsRef<Path>, T2: AsRef<Path>>(src: T1, dst: T2) -> io::Result<()> {
+    let dst = dst.as_ref();
+    let tmp = tmp_path(dst);
+    fs::copy(src, &tmp)?;
+    fs::rename(&tmp, dst)
+}
+
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut name = path.file_name().unwrap().to_owned();
+    name.push(TMP_EXTENSION);
+    path.with_file_name(name)
+}